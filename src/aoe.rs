@@ -0,0 +1,203 @@
+use std::collections::BTreeSet;
+
+use eframe::egui::{Color32, Pos2, Rect, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+use crate::fog::{self, CELL, Cell};
+use crate::vision;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum Shape {
+    /// radius around a grid corner
+    #[default]
+    Circle,
+    /// as wide at its end as it is long
+    Cone,
+    /// one cell wide, starting at the middle of a cell
+    Line,
+    /// from one grid corner to the opposite one
+    Square,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 4] = [Shape::Circle, Shape::Cone, Shape::Line, Shape::Square];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Circle => "circle",
+            Shape::Cone => "cone",
+            Shape::Line => "line",
+            Shape::Square => "square",
+        }
+    }
+}
+
+/// an area of effect on the board. it starts at a grid corner, or a cell's middle for
+/// lines, and reaches toward target, its size rounded to whole cells
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AreaTemplate {
+    pub shape: Shape,
+    pub origin: Pos2,
+    pub target: Pos2,
+    pub color: Color32,
+    pub owner: String,
+}
+
+impl AreaTemplate {
+    pub fn new(shape: Shape, origin: Pos2, target: Pos2) -> Self {
+        let origin = match shape {
+            Shape::Line => fog::cell_rect(fog::cell_of(origin)).center(),
+            _ => vision::snap_to_corner(origin),
+        };
+        AreaTemplate {
+            shape,
+            origin,
+            target,
+            color: Color32::from_rgb(230, 120, 30),
+            owner: String::new(),
+        }
+    }
+    /// the same area with both ends moved onto the board, for areas a client sent
+    pub fn clamped(&self) -> Self {
+        AreaTemplate {
+            color: self.color,
+            owner: self.owner.clone(),
+            ..AreaTemplate::new(
+                self.shape,
+                fog::clamp_to_board(self.origin),
+                fog::clamp_to_board(self.target),
+            )
+        }
+    }
+    /// reach of the area in whole cells
+    pub fn size(&self) -> i32 {
+        (self.origin.distance(self.target) / CELL).round() as i32
+    }
+    fn direction(&self) -> Vec2 {
+        (self.target - self.origin).normalized()
+    }
+    /// the outline to draw, empty for circles
+    pub fn outline(&self) -> Vec<Pos2> {
+        let length = self.size() as f32 * CELL;
+        let d = self.direction() * length;
+        let side = d.rot90();
+        match self.shape {
+            Shape::Circle => Vec::new(),
+            Shape::Cone => {
+                let end = self.origin + d;
+                vec![self.origin, end + side * 0.5, end - side * 0.5]
+            }
+            Shape::Line => {
+                // from the back edge of the first cell, so a line of n covers n cells
+                let start = self.origin - self.direction() * (CELL / 2.0);
+                let half = self.direction().rot90() * (CELL / 2.0);
+                vec![
+                    start + half,
+                    start + d + half,
+                    start + d - half,
+                    start - half,
+                ]
+            }
+            Shape::Square => {
+                let r = self.square();
+                vec![
+                    r.left_top(),
+                    r.right_top(),
+                    r.right_bottom(),
+                    r.left_bottom(),
+                ]
+            }
+        }
+    }
+    fn square(&self) -> Rect {
+        Rect::from_two_pos(self.origin, vision::snap_to_corner(self.target))
+    }
+    /// cells whose center the area covers
+    pub fn cells(&self) -> BTreeSet<Cell> {
+        if self.size() == 0 {
+            return BTreeSet::new();
+        }
+        match self.shape {
+            Shape::Circle => fog::cells_in_circle(self.origin, self.size() as f32 * CELL),
+            Shape::Square => {
+                let r = self.square();
+                if r.width() < CELL || r.height() < CELL {
+                    return BTreeSet::new();
+                }
+                fog::cells_in_rect(r)
+            }
+            Shape::Cone | Shape::Line => fog::cells_in_polygon(&self.outline()),
+        }
+        .into_iter()
+        .collect()
+    }
+}
+
+impl State {
+    pub fn allocate_area_id(&mut self) -> String {
+        self.next_area_id += 1;
+        format!("a{}", self.next_area_id)
+    }
+    /// tokens standing on a covered cell, sorted by name
+    pub fn tokens_in_area(&self, area: &AreaTemplate) -> Vec<String> {
+        let cells = area.cells();
+        let mut names: Vec<String> = self
+            .tokens
+            .iter()
+            .filter(|(_, t)| {
+                fog::cells_in_rect(Rect::from_min_size(t.location, t.size()))
+                    .iter()
+                    .any(|c| cells.contains(c))
+            })
+            .map(|(n, _)| n.clone())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+#[test]
+pub fn area_cells_test() {
+    let corner = fog::cell_rect((10, 10)).min;
+    let circle = AreaTemplate::new(
+        Shape::Circle,
+        corner + Vec2::splat(3.0),
+        corner + Vec2::new(41.0, 0.0),
+    );
+    assert_eq!(circle.origin, corner);
+    assert_eq!(circle.size(), 2);
+    assert_eq!(circle.cells().len(), 12);
+    let square = AreaTemplate::new(Shape::Square, corner, corner + Vec2::splat(60.0));
+    assert_eq!(square.cells().len(), 9);
+    let middle = fog::cell_rect((10, 10)).center();
+    let line = AreaTemplate::new(
+        Shape::Line,
+        corner + Vec2::splat(3.0),
+        middle + Vec2::new(100.0, 0.0),
+    );
+    assert_eq!(line.cells(), (10..15).map(|x| (x, 10)).collect());
+    let cone = AreaTemplate::new(Shape::Cone, corner, corner + Vec2::new(0.0, 80.0));
+    assert!(cone.cells().contains(&(10, 13)));
+    assert!(!cone.cells().contains(&(10, 9)));
+    let huge = AreaTemplate::new(Shape::Circle, corner, Pos2::new(f32::INFINITY, f32::NAN));
+    let board_end = fog::BOARD_MIN + fog::BOARD_CELLS as f32 * CELL;
+    assert_eq!(huge.clamped().target, Pos2::new(board_end, fog::BOARD_MIN));
+    assert_eq!(fog::cells_in_rect(Rect::EVERYTHING), fog::all_cells());
+    let mut state = State::new();
+    state.tokens.insert(
+        "in".into(),
+        Token {
+            location: fog::cell_rect((11, 11)).min,
+            ..Default::default()
+        },
+    );
+    state.tokens.insert(
+        "out".into(),
+        Token {
+            location: fog::cell_rect((20, 20)).min,
+            ..Default::default()
+        },
+    );
+    assert_eq!(state.tokens_in_area(&square), vec!["in".to_string()]);
+}
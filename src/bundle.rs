@@ -0,0 +1,252 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hasher},
+};
+
+use crate::communication::*;
+use crate::persistence;
+use crate::utils::Exception;
+use crate::{throw, throws};
+
+pub const BUNDLE_MAGIC: &[u8; 8] = b"BOREDPAK";
+pub const BUNDLE_EXTENSION: &str = ".boredpack";
+const STATE_ENTRY: &str = "state.bored";
+
+/// a map together with every image it references, packed into one file
+pub struct Bundle {
+    pub state: State,
+    pub assets: HashMap<String, Vec<u8>>,
+}
+
+/// every image the session shows, decks and hands of the other scenes included
+pub fn referenced_assets(state: &State) -> HashSet<String> {
+    state
+        .shown_images()
+        .chain(state.scenes.stored.values().flat_map(|s| s.shown_images()))
+        .cloned()
+        .collect()
+}
+
+/// the card images of one scene, tokens and the journal are renamed separately
+fn card_images_mut(state: &mut State) -> impl Iterator<Item = &mut String> {
+    state
+        .decks
+        .values_mut()
+        .flat_map(|d| {
+            d.cards
+                .iter_mut()
+                .chain(&mut d.discard)
+                .chain([&mut d.back])
+        })
+        .chain(state.hands.values_mut().flatten().map(|c| &mut c.image))
+        .chain(state.hidden_cards.values_mut())
+}
+
+fn push_entry(out: &mut Vec<u8>, name: &str, data: &[u8]) {
+    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+fn read_chunk<'a>(bytes: &'a [u8], at: &mut usize) -> throws!(&'a [u8]) {
+    let Some(len) = bytes.get(*at..*at + 8) else {
+        throw!("bundle is truncated");
+    };
+    let len = u64::from_le_bytes(len.try_into()?) as usize;
+    *at += 8;
+    let Some(chunk) = bytes.get(*at..(*at).saturating_add(len)) else {
+        throw!("bundle is truncated");
+    };
+    *at += len;
+    Ok(chunk)
+}
+
+/// only bare file names are kept so a bundle can never write outside the asset folder
+fn sanitize_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+fn hash_bytes(data: &[u8]) -> u64 {
+    let mut h = DefaultHasher::new();
+    h.write(data);
+    h.finish()
+}
+
+/// packs the state and every referenced image, looked up in asset_dirs in order.
+/// missing images are skipped
+pub fn export_bundle(state: &State, asset_dirs: &[&str]) -> throws!(Vec<u8>) {
+    let mut out = BUNDLE_MAGIC.to_vec();
+    push_entry(
+        &mut out,
+        STATE_ENTRY,
+        persistence::encode_save(state)?.as_bytes(),
+    );
+    let mut names: Vec<String> = referenced_assets(state).into_iter().collect();
+    names.sort_unstable();
+    for name in names {
+        match asset_dirs
+            .iter()
+            .find_map(|d| std::fs::read(d.to_string() + &name).ok())
+        {
+            Some(data) => push_entry(&mut out, &name, &data),
+            None => println!("not bundling missing {}", name),
+        }
+    }
+    Ok(out)
+}
+
+pub fn write_bundle(state: &State, asset_dirs: &[&str], target: &str) -> throws!() {
+    let bytes = export_bundle(state, asset_dirs)?;
+    persistence::write_atomic(target, &bytes)
+}
+
+pub fn read_bundle(bytes: &[u8]) -> throws!(Bundle) {
+    if !bytes.starts_with(BUNDLE_MAGIC) {
+        throw!("not a bored games bundle");
+    }
+    let mut at = BUNDLE_MAGIC.len();
+    let mut state = None;
+    let mut assets = HashMap::new();
+    while at < bytes.len() {
+        let name = String::from_utf8(read_chunk(bytes, &mut at)?.to_vec())?;
+        let data = read_chunk(bytes, &mut at)?;
+        if name == STATE_ENTRY {
+            state = Some(persistence::decode_save(std::str::from_utf8(data)?)?);
+        } else if let Some(name) = sanitize_name(&name) {
+            assets.insert(name, data.to_vec());
+        }
+    }
+    let Some(state) = state else {
+        throw!("bundle has no map in it");
+    };
+    Ok(Bundle { state, assets })
+}
+
+/// writes the bundled images into asset_dir and returns the state pointing at them.
+/// images already present with the same contents are reused, and a name that is taken
+/// by a different image gets the content hash appended
+pub fn import_bundle(bundle: Bundle, asset_dir: &str) -> throws!(State) {
+    // the hash only narrows things down, files are compared in full before one is reused
+    let mut existing: HashMap<u64, Vec<String>> = HashMap::new();
+    if let Ok(dir) = std::fs::read_dir(asset_dir) {
+        for e in dir.flatten() {
+            let name: String = e.file_name().to_string_lossy().into();
+            if name.ends_with(persistence::SAVE_EXTENSION) || name.ends_with(BUNDLE_EXTENSION) {
+                continue;
+            }
+            if e.file_type().is_ok_and(|t| t.is_file())
+                && let Ok(data) = std::fs::read(e.path())
+            {
+                existing.entry(hash_bytes(&data)).or_default().push(name);
+            }
+        }
+    }
+    let same_as = |name: &String, data: &[u8]| {
+        std::fs::read(asset_dir.to_string() + name).is_ok_and(|d| d == data)
+    };
+    let mut renames = HashMap::new();
+    for (name, data) in &bundle.assets {
+        let hash = hash_bytes(data);
+        let found = existing
+            .get(&hash)
+            .and_then(|names| names.iter().find(|n| same_as(n, data)));
+        if let Some(found) = found {
+            renames.insert(name.clone(), found.clone());
+            continue;
+        }
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) => (stem, format!(".{}", ext)),
+            None => (name.as_str(), String::new()),
+        };
+        let mut target = name.clone();
+        let mut n = 0;
+        while std::fs::exists(asset_dir.to_string() + &target)? {
+            n += 1;
+            target = if n == 1 {
+                format!("{}_{:x}{}", stem, hash, ext)
+            } else {
+                format!("{}_{:x}_{}{}", stem, hash, n, ext)
+            };
+        }
+        persistence::write_atomic(&(asset_dir.to_string() + &target), data)?;
+        existing.entry(hash).or_default().push(target.clone());
+        renames.insert(name.clone(), target);
+    }
+    // names the bundle has no image for are still cut down to bare file names
+    let rename = |image: &mut String| {
+        if image.is_empty() {
+            return;
+        }
+        *image = match renames.get(image) {
+            Some(n) => n.clone(),
+            None => sanitize_name(image).unwrap_or_default(),
+        };
+    };
+    let mut state = bundle.state;
+    for t in state.session_tokens_mut() {
+        rename(&mut t.image);
+    }
+    for image in state.journal.values_mut().flat_map(|e| e.images.iter_mut()) {
+        rename(image);
+    }
+    for image in card_images_mut(&mut state) {
+        rename(image);
+    }
+    for scene in state.scenes.stored.values_mut() {
+        for image in card_images_mut(scene) {
+            rename(image);
+        }
+    }
+    Ok(state)
+}
+
+#[test]
+pub fn bundle_roundtrip_test() -> throws!() {
+    let dir = std::env::temp_dir().join("boredgames_bundle_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("a"))?;
+    std::fs::create_dir_all(dir.join("b"))?;
+    let a = dir.join("a").to_string_lossy().to_string() + "/";
+    let b = dir.join("b").to_string_lossy().to_string() + "/";
+    std::fs::write(a.clone() + "orc.png", b"orc")?;
+    std::fs::write(b.clone() + "orc.png", b"not an orc")?;
+    std::fs::write(b.clone() + "copy.png", b"orc")?;
+    let mut state = State::new();
+    state.tokens.insert(
+        "t".into(),
+        Token {
+            image: "orc.png".into(),
+            ..Default::default()
+        },
+    );
+    state.tokens.insert(
+        "escape".into(),
+        Token {
+            image: "../../secret.png".into(),
+            ..Default::default()
+        },
+    );
+    std::fs::write(a.clone() + "ace.png", b"ace")?;
+    let mut other = State::new();
+    other.decks.insert(
+        "d".into(),
+        crate::cards::Deck {
+            cards: vec!["ace.png".into()],
+            ..Default::default()
+        },
+    );
+    state.scenes.stored.insert("other".into(), other);
+    std::fs::write(b.clone() + "old.bored", b"orc")?;
+    let bytes = export_bundle(&state, &[&a])?;
+    let imported = import_bundle(read_bundle(&bytes)?, &b)?;
+    assert_eq!(imported.tokens["t"].image, "copy.png");
+    assert_eq!(std::fs::read(b.clone() + "ace.png")?, b"ace");
+    assert_eq!(imported.tokens["escape"].image, "secret.png");
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use eframe::egui::Pos2;
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+use crate::utils::Rng;
+
+/// a pile of cards on the board, the last card of the draw pile is the top
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Deck {
+    pub name: String,
+    pub location: Pos2,
+    /// image shown for the deck and for cards played face down
+    pub back: String,
+    /// images of the cards left to draw. clients only ever get empty names here
+    pub cards: Vec<String>,
+    /// face up and public
+    pub discard: Vec<String>,
+    /// seed of the last shuffle, so a shuffle can be replayed. the server keeps it to itself,
+    /// with it and the card names anyone could work out the draw order
+    pub seed: u64,
+}
+
+/// a card in someone's hand
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HandCard {
+    pub deck: String,
+    /// empty for cards in other people's hands
+    pub image: String,
+}
+
+/// where a card is taken from
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum CardPlace {
+    Hand { index: usize },
+    Board { name: String },
+}
+
+/// fisher-yates with the seeded rng, the same seed always gives the same order
+pub fn shuffle(cards: &mut [String], seed: u64) {
+    let mut rng = Rng::new(seed);
+    for i in (1..cards.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        cards.swap(i, j);
+    }
+}
+
+impl State {
+    pub fn allocate_deck_id(&mut self) -> String {
+        self.next_deck_id += 1;
+        format!("d{}", self.next_deck_id)
+    }
+    pub fn shuffle_deck(&mut self, deck: &String, seed: u64, with_discards: bool) {
+        let Some(d) = self.decks.get_mut(deck) else {
+            return;
+        };
+        if with_discards {
+            d.cards.append(&mut d.discard);
+        }
+        d.seed = seed;
+        shuffle(&mut d.cards, seed);
+    }
+    pub fn draw_card(&mut self, deck: &String, user: &str) -> bool {
+        let Some(image) = self.decks.get_mut(deck).and_then(|d| d.cards.pop()) else {
+            return false;
+        };
+        self.hands
+            .entry(user.to_string())
+            .or_default()
+            .push(HandCard {
+                deck: deck.clone(),
+                image,
+            });
+        true
+    }
+    /// takes a card out of a hand or off the board, returning its deck and real image.
+    /// only the gm and the token's owner may take a card off the board
+    pub fn take_card(&mut self, from: &CardPlace, user: &String, gm: &String) -> Option<HandCard> {
+        match from {
+            CardPlace::Hand { index } => {
+                let hand = self.hands.get_mut(user)?;
+                (*index < hand.len()).then(|| hand.remove(*index))
+            }
+            CardPlace::Board { name } => {
+                if !self.controls(name, user, gm) {
+                    return None;
+                }
+                let deck = self.get_token(name).map(|t| t.card.clone())?;
+                if deck.is_empty() {
+                    return None;
+                }
+                let image = match self.hidden_cards.get(name) {
+                    Some(image) => image.clone(),
+                    None => self.get_token(name)?.image.clone(),
+                };
+                // a card on the board can't also be waiting in its deck
+                let d = self.decks.get(&deck)?;
+                if image.is_empty() || d.cards.contains(&image) || d.discard.contains(&image) {
+                    return None;
+                }
+                self.hidden_cards.remove(name);
+                self.destroy_token(name);
+                Some(HandCard { deck, image })
+            }
+        }
+    }
+    pub fn discard_card(&mut self, card: HandCard) {
+        if let Some(d) = self.decks.get_mut(&card.deck) {
+            d.discard.push(card.image);
+        }
+    }
+    /// slides a card back under the deck
+    pub fn return_card(&mut self, card: HandCard) {
+        if let Some(d) = self.decks.get_mut(&card.deck) {
+            d.cards.insert(0, card.image);
+        }
+    }
+    /// puts a card on the board as a token, face down ones show the deck's back
+    pub fn play_card(&mut self, card: HandCard, face_up: bool, location: Pos2, user: &str) {
+        let Some(back) = self.decks.get(&card.deck).map(|d| d.back.clone()) else {
+            return;
+        };
+        let id = self.allocate_token_id();
+        let token = Token {
+            location,
+            width: 2,
+            height: 3,
+            image: if face_up { card.image.clone() } else { back },
+            owner: user.to_string(),
+            card: card.deck,
+            ..Default::default()
+        };
+        if !face_up {
+            self.hidden_cards.insert(id.clone(), card.image);
+        }
+        self.create_token(id, token, &Layer::Base);
+    }
+    /// turns a card on the board over, its image goes into hiding or comes back out
+    pub fn flip_card(&mut self, name: &String) {
+        let Some(mut token) = self.get_token(name).cloned() else {
+            return;
+        };
+        let Some(back) = self.decks.get(&token.card).map(|d| d.back.clone()) else {
+            return;
+        };
+        match self.hidden_cards.remove(name) {
+            Some(image) => token.image = image,
+            None => {
+                let image = std::mem::replace(&mut token.image, back);
+                self.hidden_cards.insert(name.clone(), image);
+            }
+        }
+        self.update_token(name, &token);
+    }
+    /// what a client may know: other people's hands, the draw piles and
+    /// face down cards go out without their images
+    pub fn hide_cards(&mut self, user: &String) {
+        for (owner, hand) in self.hands.iter_mut() {
+            if owner != user {
+                for c in hand {
+                    c.image.clear();
+                }
+            }
+        }
+        for d in self.decks.values_mut() {
+            for c in &mut d.cards {
+                c.clear();
+            }
+            d.seed = 0;
+        }
+        for image in self.hidden_cards.values_mut() {
+            image.clear();
+        }
+    }
+    /// the state as one client gets to see it
+    pub fn for_client(&self, user: &String, gm: bool) -> State {
+        let mut out = if gm { self.clone() } else { self.for_players() };
+        out.hide_cards(user);
+        out.hide_journal(user, gm);
+        out.hide_sheets(user, gm);
+        out
+    }
+    /// every image the state shows. on a state from for_client these are the only
+    /// images its client gets sent
+    pub fn shown_images(&self) -> impl Iterator<Item = &String> {
+        let decks = self
+            .decks
+            .values()
+            .flat_map(|d| d.cards.iter().chain(&d.discard).chain([&d.back]));
+        self.session_tokens()
+            .map(|t| &t.image)
+            .chain(decks)
+            .chain(self.hands.values().flatten().map(|c| &c.image))
+            .chain(self.hidden_cards.values())
+            .chain(self.journal_images())
+            .filter(|i| !i.is_empty())
+    }
+}
+
+#[test]
+pub fn hidden_cards_test() {
+    let mut state = State::new();
+    let cards: Vec<String> = (0..10).map(|i| format!("{}.png", i)).collect();
+    let (mut a, mut b) = (cards.clone(), cards.clone());
+    shuffle(&mut a, 7);
+    shuffle(&mut b, 7);
+    assert_eq!(a, b);
+    assert_ne!(a, cards);
+    state.decks.insert(
+        "d".into(),
+        Deck {
+            back: "back.png".into(),
+            cards: a,
+            seed: 7,
+            ..Default::default()
+        },
+    );
+    let (alice, bob) = (String::from("alice"), String::from("bob"));
+    assert!(state.draw_card(&"d".into(), &alice));
+    assert!(state.draw_card(&"d".into(), &alice));
+    let card = state
+        .take_card(&CardPlace::Hand { index: 0 }, &alice, &bob)
+        .unwrap();
+    let image = card.image.clone();
+    state.play_card(card, false, Pos2::new(200.0, 200.0), &alice);
+    let seen = state.for_client(&bob, false);
+    let text = serde_json::to_string(&seen).unwrap();
+    assert!(!text.contains(&image));
+    assert!(seen.hands[&alice].iter().all(|c| c.image.is_empty()));
+    assert!(seen.decks["d"].cards.iter().all(|c| c.is_empty()));
+    assert_eq!(seen.decks["d"].seed, 0);
+    let own = state.for_client(&alice, false);
+    assert!(own.hands[&alice].iter().all(|c| !c.image.is_empty()));
+    let name = state.tokens.keys().next().unwrap().clone();
+    state.flip_card(&name);
+    assert_eq!(state.tokens[&name].image, image);
+    // a token claiming to be a card still in the deck can't be taken into a hand
+    let forged = state.decks["d"].cards[0].clone();
+    state.tokens.insert(
+        "forged".into(),
+        Token {
+            image: forged,
+            card: "d".into(),
+            owner: bob.clone(),
+            ..Default::default()
+        },
+    );
+    let board = |name: &str| CardPlace::Board { name: name.into() };
+    assert!(state.take_card(&board("forged"), &bob, &alice).is_none());
+    assert!(state.take_card(&board(&name), &alice, &bob).is_some());
+}
@@ -0,0 +1,1007 @@
+use eframe::egui::{Color32, Pos2};
+
+use crate::communication::*;
+use crate::fog::{BOARD_MIN, CELL};
+use crate::rules::{Game, GameRules, Outcome};
+
+pub const CHESS: &str = "chess";
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+/// a square is two grid cells wide
+pub const SQUARE: f32 = CELL * 2.0;
+/// top left corner of a8, the board sits in the middle of the grid
+pub const ORIGIN: f32 = BOARD_MIN + 11.0 * CELL;
+
+/// file then rank, both 0 to 7, a1 is (0, 0)
+pub type Square = (i8, i8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    White,
+    Black,
+}
+impl Side {
+    pub fn other(self) -> Self {
+        match self {
+            Side::White => Side::Black,
+            Side::Black => Side::White,
+        }
+    }
+    fn forward(self) -> i8 {
+        match self {
+            Side::White => 1,
+            Side::Black => -1,
+        }
+    }
+    fn home(self) -> i8 {
+        match self {
+            Side::White => 0,
+            Side::Black => 7,
+        }
+    }
+    /// seat in the game's player list
+    pub fn index(self) -> usize {
+        match self {
+            Side::White => 0,
+            Side::Black => 1,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::White => "white",
+            Side::Black => "black",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    King,
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+    Pawn,
+}
+impl Kind {
+    pub const PROMOTIONS: [Kind; 4] = [Kind::Queen, Kind::Rook, Kind::Bishop, Kind::Knight];
+    pub fn letter(self) -> char {
+        match self {
+            Kind::King => 'K',
+            Kind::Queen => 'Q',
+            Kind::Rook => 'R',
+            Kind::Bishop => 'B',
+            Kind::Knight => 'N',
+            Kind::Pawn => 'P',
+        }
+    }
+    pub fn from_letter(c: char) -> Option<Kind> {
+        Some(match c.to_ascii_uppercase() {
+            'K' => Kind::King,
+            'Q' => Kind::Queen,
+            'R' => Kind::Rook,
+            'B' => Kind::Bishop,
+            'N' => Kind::Knight,
+            'P' => Kind::Pawn,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Piece {
+    pub side: Side,
+    pub kind: Kind,
+}
+impl Piece {
+    fn fen_char(self) -> char {
+        match self.side {
+            Side::White => self.kind.letter(),
+            Side::Black => self.kind.letter().to_ascii_lowercase(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Option<Kind>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Playing,
+    Checkmate,
+    Stalemate,
+    FiftyMoves,
+    InsufficientMaterial,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position {
+    /// indexed by rank then file
+    pub board: [[Option<Piece>; 8]; 8],
+    pub to_move: Side,
+    /// white king side, white queen side, black king side, black queen side
+    pub castling: [bool; 4],
+    /// the square a pawn skipped over on the last move
+    pub en_passant: Option<Square>,
+    pub halfmove: u32,
+    pub fullmove: u32,
+}
+
+const KNIGHT: [Square; 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING: [Square; 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+const ROOK_DIRS: [Square; 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRS: [Square; 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+pub fn on_board(s: Square) -> bool {
+    (0..8).contains(&s.0) && (0..8).contains(&s.1)
+}
+
+pub fn square_name(s: Square) -> String {
+    format!("{}{}", (b'a' + s.0 as u8) as char, s.1 + 1)
+}
+
+pub fn parse_square(text: &str) -> Option<Square> {
+    let mut c = text.chars();
+    let file = c.next().filter(|f| f.is_ascii_lowercase())? as i8 - b'a' as i8;
+    let rank = c.next()?.to_digit(10)? as i8 - 1;
+    let s = (file, rank);
+    (c.next().is_none() && on_board(s)).then_some(s)
+}
+
+/// where a piece on the square sits on screen
+pub fn square_pos(s: Square) -> Pos2 {
+    Pos2::new(
+        ORIGIN + s.0 as f32 * SQUARE,
+        ORIGIN + (7 - s.1) as f32 * SQUARE,
+    )
+}
+
+/// the square a token at p is closest to, if it is on the chess board
+pub fn square_at(p: Pos2) -> Option<Square> {
+    let file = ((p.x - ORIGIN) / SQUARE).round() as i8;
+    let rank = 7 - ((p.y - ORIGIN) / SQUARE).round() as i8;
+    let s = (file, rank);
+    on_board(s).then_some(s)
+}
+
+impl Position {
+    pub fn start() -> Self {
+        Self::from_fen(START_FEN).unwrap()
+    }
+    pub fn get(&self, s: Square) -> Option<Piece> {
+        self.board[s.1 as usize][s.0 as usize]
+    }
+    fn set(&mut self, s: Square, p: Option<Piece>) {
+        self.board[s.1 as usize][s.0 as usize] = p;
+    }
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        if parts.len() < 4 {
+            return Err(format!("not a FEN: {}", fen));
+        }
+        let mut board = [[None; 8]; 8];
+        let ranks: Vec<&str> = parts[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err("a FEN board needs 8 ranks".into());
+        }
+        for (i, row) in ranks.iter().enumerate() {
+            let rank = 7 - i;
+            let mut file = 0;
+            for c in row.chars() {
+                if let Some(d) = c.to_digit(10) {
+                    file += d as usize;
+                    continue;
+                }
+                let kind = Kind::from_letter(c).ok_or(format!("bad piece {} in FEN", c))?;
+                if file >= 8 {
+                    return Err(format!("rank {} is too long", rank + 1));
+                }
+                let side = if c.is_ascii_uppercase() {
+                    Side::White
+                } else {
+                    Side::Black
+                };
+                board[rank][file] = Some(Piece { side, kind });
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("rank {} doesn't have 8 squares", rank + 1));
+            }
+        }
+        let to_move = match parts[1] {
+            "w" => Side::White,
+            "b" => Side::Black,
+            s => return Err(format!("bad side to move {}", s)),
+        };
+        let mut castling = [false; 4];
+        for c in parts[2].chars() {
+            match c {
+                'K' => castling[0] = true,
+                'Q' => castling[1] = true,
+                'k' => castling[2] = true,
+                'q' => castling[3] = true,
+                '-' => {}
+                _ => return Err(format!("bad castling rights {}", parts[2])),
+            }
+        }
+        let en_passant = match parts[3] {
+            "-" => None,
+            s => Some(parse_square(s).ok_or(format!("bad en passant square {}", s))?),
+        };
+        let number = |i: usize, default: u32| {
+            parts
+                .get(i)
+                .map(|s| s.parse().map_err(|_| format!("bad move counter {}", s)))
+                .unwrap_or(Ok(default))
+        };
+        let pos = Self {
+            board,
+            to_move,
+            castling,
+            en_passant,
+            halfmove: number(4, 0)?,
+            fullmove: number(5, 1)?,
+        };
+        for side in [Side::White, Side::Black] {
+            if pos.king(side).is_none() {
+                return Err(format!("{} has no king", side.name()));
+            }
+        }
+        Ok(pos)
+    }
+    pub fn to_fen(&self) -> String {
+        let mut out = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.get((file, rank)) {
+                    Some(p) => {
+                        if empty > 0 {
+                            out += &empty.to_string();
+                            empty = 0;
+                        }
+                        out.push(p.fen_char());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                out += &empty.to_string();
+            }
+            if rank > 0 {
+                out.push('/');
+            }
+        }
+        out += match self.to_move {
+            Side::White => " w ",
+            Side::Black => " b ",
+        };
+        let rights: String = "KQkq"
+            .chars()
+            .zip(self.castling)
+            .filter(|(_, on)| *on)
+            .map(|(c, _)| c)
+            .collect();
+        out += if rights.is_empty() { "-" } else { &rights };
+        out += " ";
+        out += &self.en_passant.map(square_name).unwrap_or("-".into());
+        out + &format!(" {} {}", self.halfmove, self.fullmove)
+    }
+    pub fn king(&self, side: Side) -> Option<Square> {
+        (0..8).flat_map(|r| (0..8).map(move |f| (f, r))).find(|s| {
+            self.get(*s)
+                == Some(Piece {
+                    side,
+                    kind: Kind::King,
+                })
+        })
+    }
+    /// whether any piece of side by attacks the square
+    pub fn attacked(&self, s: Square, by: Side) -> bool {
+        let is = |sq: Square, kinds: &[Kind]| {
+            on_board(sq)
+                && self
+                    .get(sq)
+                    .is_some_and(|p| p.side == by && kinds.contains(&p.kind))
+        };
+        let f = by.forward();
+        if is((s.0 - 1, s.1 - f), &[Kind::Pawn]) || is((s.0 + 1, s.1 - f), &[Kind::Pawn]) {
+            return true;
+        }
+        if KNIGHT
+            .iter()
+            .any(|d| is((s.0 + d.0, s.1 + d.1), &[Kind::Knight]))
+        {
+            return true;
+        }
+        if KING
+            .iter()
+            .any(|d| is((s.0 + d.0, s.1 + d.1), &[Kind::King]))
+        {
+            return true;
+        }
+        for (dirs, kinds) in [
+            (ROOK_DIRS, [Kind::Rook, Kind::Queen]),
+            (BISHOP_DIRS, [Kind::Bishop, Kind::Queen]),
+        ] {
+            for d in dirs {
+                let mut sq = (s.0 + d.0, s.1 + d.1);
+                while on_board(sq) {
+                    if let Some(p) = self.get(sq) {
+                        if p.side == by && kinds.contains(&p.kind) {
+                            return true;
+                        }
+                        break;
+                    }
+                    sq = (sq.0 + d.0, sq.1 + d.1);
+                }
+            }
+        }
+        false
+    }
+    pub fn in_check(&self, side: Side) -> bool {
+        self.king(side)
+            .is_some_and(|k| self.attacked(k, side.other()))
+    }
+    /// moves that follow how the pieces move but may leave the king in check
+    fn pseudo_moves(&self) -> Vec<Move> {
+        let side = self.to_move;
+        let mut out = Vec::new();
+        let mut push = |from: Square, to: Square, promote: bool| {
+            if promote {
+                for k in Kind::PROMOTIONS {
+                    out.push(Move {
+                        from,
+                        to,
+                        promotion: Some(k),
+                    });
+                }
+            } else {
+                out.push(Move {
+                    from,
+                    to,
+                    promotion: None,
+                });
+            }
+        };
+        for rank in 0..8 {
+            for file in 0..8 {
+                let from = (file, rank);
+                let Some(piece) = self.get(from).filter(|p| p.side == side) else {
+                    continue;
+                };
+                let free = |s: Square| on_board(s) && self.get(s).is_none();
+                let enemy = |s: Square| on_board(s) && self.get(s).is_some_and(|p| p.side != side);
+                match piece.kind {
+                    Kind::Pawn => {
+                        let f = side.forward();
+                        let last = side.other().home();
+                        let one = (file, rank + f);
+                        if free(one) {
+                            push(from, one, one.1 == last);
+                            let two = (file, rank + 2 * f);
+                            if rank == side.home() + f && free(two) {
+                                push(from, two, false);
+                            }
+                        }
+                        for df in [-1, 1] {
+                            let to = (file + df, rank + f);
+                            if enemy(to) || (on_board(to) && self.en_passant == Some(to)) {
+                                push(from, to, to.1 == last);
+                            }
+                        }
+                    }
+                    Kind::Knight | Kind::King => {
+                        let steps = if piece.kind == Kind::Knight {
+                            KNIGHT
+                        } else {
+                            KING
+                        };
+                        for d in steps {
+                            let to = (file + d.0, rank + d.1);
+                            if free(to) || enemy(to) {
+                                push(from, to, false);
+                            }
+                        }
+                    }
+                    Kind::Rook | Kind::Bishop | Kind::Queen => {
+                        let dirs: Vec<Square> = match piece.kind {
+                            Kind::Rook => ROOK_DIRS.to_vec(),
+                            Kind::Bishop => BISHOP_DIRS.to_vec(),
+                            _ => [ROOK_DIRS, BISHOP_DIRS].concat(),
+                        };
+                        for d in dirs {
+                            let mut to = (file + d.0, rank + d.1);
+                            while free(to) {
+                                push(from, to, false);
+                                to = (to.0 + d.0, to.1 + d.1);
+                            }
+                            if enemy(to) {
+                                push(from, to, false);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // castling, the king may not start in, pass through or land in check
+        let r = side.home();
+        let rights = match side {
+            Side::White => [self.castling[0], self.castling[1]],
+            Side::Black => [self.castling[2], self.castling[3]],
+        };
+        let king = Some(Piece {
+            side,
+            kind: Kind::King,
+        });
+        let rook = Some(Piece {
+            side,
+            kind: Kind::Rook,
+        });
+        let safe = |files: &[i8]| files.iter().all(|f| !self.attacked((*f, r), side.other()));
+        let empty = |files: &[i8]| files.iter().all(|f| self.get((*f, r)).is_none());
+        if self.get((4, r)) == king {
+            if rights[0] && self.get((7, r)) == rook && empty(&[5, 6]) && safe(&[4, 5, 6]) {
+                push((4, r), (6, r), false);
+            }
+            if rights[1] && self.get((0, r)) == rook && empty(&[1, 2, 3]) && safe(&[4, 3, 2]) {
+                push((4, r), (2, r), false);
+            }
+        }
+        out
+    }
+    pub fn legal_moves(&self) -> Vec<Move> {
+        self.pseudo_moves()
+            .into_iter()
+            .filter(|m| !self.play(m).in_check(self.to_move))
+            .collect()
+    }
+    fn is_castle(&self, m: &Move) -> bool {
+        self.get(m.from).is_some_and(|p| p.kind == Kind::King) && (m.to.0 - m.from.0).abs() == 2
+    }
+    fn is_en_passant(&self, m: &Move) -> bool {
+        self.get(m.from).is_some_and(|p| p.kind == Kind::Pawn)
+            && m.from.0 != m.to.0
+            && self.get(m.to).is_none()
+    }
+    /// the square whose piece the move takes, if any
+    pub fn captured(&self, m: &Move) -> Option<Square> {
+        if self.is_en_passant(m) {
+            Some((m.to.0, m.from.1))
+        } else {
+            self.get(m.to).map(|_| m.to)
+        }
+    }
+    /// for a castling move, where the rook goes from and to
+    pub fn castle_rook(&self, m: &Move) -> Option<(Square, Square)> {
+        if !self.is_castle(m) {
+            return None;
+        }
+        let r = m.from.1;
+        Some(if m.to.0 == 6 {
+            ((7, r), (5, r))
+        } else {
+            ((0, r), (3, r))
+        })
+    }
+    /// the position after a move, without checking that it is legal
+    pub fn play(&self, m: &Move) -> Position {
+        let mut p = self.clone();
+        let Some(mut piece) = self.get(m.from) else {
+            return p;
+        };
+        let capture = self.captured(m);
+        if let Some(c) = capture {
+            p.set(c, None);
+        }
+        if let Some((a, b)) = self.castle_rook(m) {
+            p.set(b, p.get(a));
+            p.set(a, None);
+        }
+        if let Some(k) = m.promotion {
+            piece.kind = k;
+        }
+        p.set(m.from, None);
+        p.set(m.to, Some(piece));
+        if piece.kind == Kind::King {
+            let i = piece.side.index() * 2;
+            p.castling[i] = false;
+            p.castling[i + 1] = false;
+        }
+        for (corner, i) in [((7, 0), 0), ((0, 0), 1), ((7, 7), 2), ((0, 7), 3)] {
+            if m.from == corner || m.to == corner {
+                p.castling[i] = false;
+            }
+        }
+        let pawn = self.get(m.from).is_some_and(|p| p.kind == Kind::Pawn);
+        p.en_passant =
+            (pawn && (m.to.1 - m.from.1).abs() == 2).then_some((m.from.0, (m.from.1 + m.to.1) / 2));
+        p.halfmove = if pawn || capture.is_some() {
+            0
+        } else {
+            self.halfmove + 1
+        };
+        if self.to_move == Side::Black {
+            p.fullmove += 1;
+        }
+        p.to_move = self.to_move.other();
+        p
+    }
+    fn insufficient_material(&self) -> bool {
+        let rest: Vec<Kind> = self
+            .board
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|p| p.kind)
+            .filter(|k| *k != Kind::King)
+            .collect();
+        match rest[..] {
+            [] => true,
+            [k] => k == Kind::Bishop || k == Kind::Knight,
+            _ => false,
+        }
+    }
+    pub fn status(&self) -> Status {
+        if self.legal_moves().is_empty() {
+            if self.in_check(self.to_move) {
+                Status::Checkmate
+            } else {
+                Status::Stalemate
+            }
+        } else if self.halfmove >= 100 {
+            Status::FiftyMoves
+        } else if self.insufficient_material() {
+            Status::InsufficientMaterial
+        } else {
+            Status::Playing
+        }
+    }
+    /// standard algebraic notation of a legal move, e.g. Nbd7, exd6, O-O, e8=Q+
+    pub fn san(&self, m: &Move) -> String {
+        let mut out = if let Some((_, rook_to)) = self.castle_rook(m) {
+            if rook_to.0 == 5 { "O-O" } else { "O-O-O" }.to_string()
+        } else {
+            let Some(piece) = self.get(m.from) else {
+                return String::new();
+            };
+            let mut out = String::new();
+            let capture = self.captured(m).is_some();
+            if piece.kind == Kind::Pawn {
+                if capture {
+                    out.push((b'a' + m.from.0 as u8) as char);
+                }
+            } else {
+                out.push(piece.kind.letter());
+                let rivals: Vec<Square> = self
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|o| o.to == m.to && o.from != m.from)
+                    .filter(|o| self.get(o.from) == Some(piece))
+                    .map(|o| o.from)
+                    .collect();
+                if !rivals.is_empty() {
+                    let name = square_name(m.from);
+                    if rivals.iter().all(|r| r.0 != m.from.0) {
+                        out.push_str(&name[..1]);
+                    } else if rivals.iter().all(|r| r.1 != m.from.1) {
+                        out.push_str(&name[1..]);
+                    } else {
+                        out.push_str(&name);
+                    }
+                }
+            }
+            if capture {
+                out.push('x');
+            }
+            out += &square_name(m.to);
+            if let Some(k) = m.promotion {
+                out.push('=');
+                out.push(k.letter());
+            }
+            out
+        };
+        let after = self.play(m);
+        if after.in_check(after.to_move) {
+            out.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        out
+    }
+    /// the legal move written in algebraic notation, check marks and annotations are ignored
+    pub fn parse_san(&self, text: &str) -> Option<Move> {
+        let clean = |s: &str| {
+            s.trim_end_matches(['+', '#', '!', '?'])
+                .replace('0', "O")
+                .replace('=', "")
+        };
+        let want = clean(text);
+        self.legal_moves()
+            .into_iter()
+            .find(|m| clean(&self.san(m)) == want)
+    }
+}
+
+/// reads a PGN game, returning the position it starts from and its moves
+pub fn parse_pgn(text: &str) -> Result<(Position, Vec<Move>), String> {
+    let mut start = Position::start();
+    let mut body = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(tag) = line.strip_prefix('[') {
+            if let Some(fen) = tag
+                .strip_prefix("FEN \"")
+                .and_then(|t| t.strip_suffix("\"]"))
+            {
+                start = Position::from_fen(fen)?;
+            }
+        } else {
+            // ; comments run to the end of the line
+            body += line.split(';').next().unwrap_or("");
+            body.push(' ');
+        }
+    }
+    // drop {comments} and (variations)
+    let mut depth = 0;
+    let body: String = body
+        .chars()
+        .filter(|c| {
+            match c {
+                '{' | '(' => depth += 1,
+                '}' | ')' => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => {}
+            }
+            depth == 0
+        })
+        .collect();
+    let mut pos = start.clone();
+    let mut moves = Vec::new();
+    for word in body.split_whitespace() {
+        // move numbers can be glued to the move, like 12.e4 or 12...Nf6
+        let word = word.rsplit('.').next().unwrap_or(word);
+        if word.is_empty()
+            || word.starts_with('$')
+            || ["1-0", "0-1", "1/2-1/2", "*"].contains(&word)
+        {
+            continue;
+        }
+        let m = pos
+            .parse_san(word)
+            .ok_or(format!("illegal move {} in PGN", word))?;
+        pos = pos.play(&m);
+        moves.push(m);
+    }
+    Ok((start, moves))
+}
+
+/// the game so far as PGN
+pub fn export_pgn(game: &Game) -> String {
+    let side_name = |s: Side| {
+        game.players
+            .get(s.index())
+            .cloned()
+            .unwrap_or(s.name().into())
+    };
+    let result = match &game.outcome {
+        Some(Outcome::Win { player }) if *player == side_name(Side::White) => "1-0",
+        Some(Outcome::Win { .. }) => "0-1",
+        Some(Outcome::Draw) => "1/2-1/2",
+        None => "*",
+    };
+    let mut out = format!(
+        "[Event \"boredgames\"]\n[White \"{}\"]\n[Black \"{}\"]\n[Result \"{}\"]\n",
+        side_name(Side::White),
+        side_name(Side::Black),
+        result
+    );
+    let start = Position::from_fen(&game.setup).unwrap_or(Position::start());
+    if start != Position::start() {
+        out += &format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", start.to_fen());
+    }
+    out.push('\n');
+    let (mut number, mut side) = (start.fullmove, start.to_move);
+    for (i, san) in game.record.iter().enumerate() {
+        if side == Side::White {
+            out += &format!("{}. ", number);
+        } else if i == 0 {
+            out += &format!("{}... ", number);
+        }
+        out += san;
+        out.push(' ');
+        if side == Side::Black {
+            number += 1;
+        }
+        side = side.other();
+    }
+    out + result
+}
+
+/// chess on the middle of the grid. pieces are the tokens on the token layer,
+/// and which piece a token is comes from the position kept in the game, not the token
+pub struct Chess;
+
+impl Chess {
+    fn position(state: &State) -> Result<Position, String> {
+        Position::from_fen(&state.game.position)
+    }
+    fn token_at(state: &State, s: Square, except: &String) -> Option<String> {
+        state
+            .tokens
+            .iter()
+            .find(|(n, t)| *n != except && square_at(t.location) == Some(s))
+            .map(|(n, _)| n.clone())
+    }
+    /// the legal move a token drag stands for
+    fn resolve(
+        state: &State,
+        name: &String,
+        to: &Token,
+        choice: &str,
+    ) -> Result<(Position, Move), String> {
+        let pos = Self::position(state)?;
+        let Some(token) = state.tokens.get(name) else {
+            return Err("only pieces can move".into());
+        };
+        let from = square_at(token.location).ok_or("that piece is off the board")?;
+        let dest = square_at(to.location).ok_or("pieces have to stay on the board")?;
+        match pos.get(from) {
+            Some(p) if p.side == pos.to_move => {}
+            Some(_) => return Err(format!("it is {}'s move", pos.to_move.name())),
+            None => return Err("there is no piece there".into()),
+        }
+        // a pawn promotes to the piece whose letter the mover picked, a queen if they didn't
+        let promotion = choice
+            .chars()
+            .next()
+            .and_then(Kind::from_letter)
+            .filter(|k| Kind::PROMOTIONS.contains(k))
+            .unwrap_or(Kind::Queen);
+        pos.legal_moves()
+            .into_iter()
+            .filter(|m| m.from == from && m.to == dest)
+            .find(|m| m.promotion.is_none() || m.promotion == Some(promotion))
+            .map(|m| (pos.clone(), m))
+            .ok_or(format!(
+                "{} to {} is not a legal move",
+                square_name(from),
+                square_name(dest)
+            ))
+    }
+    fn outcome(state: &mut State, pos: &Position) {
+        let seat = |s: Side| {
+            state
+                .game
+                .players
+                .get(s.index())
+                .cloned()
+                .unwrap_or(s.name().into())
+        };
+        state.game.outcome = match pos.status() {
+            Status::Playing => None,
+            Status::Checkmate => Some(Outcome::Win {
+                player: seat(pos.to_move.other()),
+            }),
+            _ => Some(Outcome::Draw),
+        };
+    }
+    fn set_turn(state: &mut State, pos: &Position) {
+        state.game.turn = if state.game.players.len() >= 2 {
+            pos.to_move.index()
+        } else {
+            0
+        };
+    }
+    /// replaces everything on the board with the squares and the pieces of the position
+    fn lay_out(state: &mut State, pos: &Position) {
+        state.tokens.clear();
+        state.map.clear();
+        for rank in 0..8 {
+            for file in 0..8 {
+                let s = (file, rank);
+                let id = state.allocate_token_id();
+                let tint = if (file + rank) % 2 == 1 {
+                    Color32::from_rgb(240, 217, 181)
+                } else {
+                    Color32::from_rgb(181, 136, 99)
+                };
+                state.create_token(
+                    id,
+                    Token {
+                        location: square_pos(s),
+                        width: 2,
+                        height: 2,
+                        tint,
+                        ..Default::default()
+                    },
+                    &Layer::Map,
+                );
+                let Some(piece) = pos.get(s) else {
+                    continue;
+                };
+                let id = state.allocate_token_id();
+                let (tint, owner) = match piece.side {
+                    Side::White => (Color32::from_gray(235), state.game.players.first()),
+                    Side::Black => (Color32::from_gray(40), state.game.players.get(1)),
+                };
+                let owner = owner.cloned().unwrap_or_default();
+                state.create_token(
+                    id,
+                    Token {
+                        location: square_pos(s),
+                        width: 2,
+                        height: 2,
+                        tint,
+                        display_name: piece.kind.letter().to_string(),
+                        owner,
+                        ..Default::default()
+                    },
+                    &Layer::Base,
+                );
+            }
+        }
+    }
+}
+
+impl GameRules for Chess {
+    fn name(&self) -> &'static str {
+        CHESS
+    }
+    /// from is empty for a new game, or a FEN or PGN to carry on from
+    fn setup(&mut self, state: &mut State, from: &str) -> Result<(), String> {
+        let from = from.trim();
+        let (start, moves) = if from.is_empty() {
+            (Position::start(), Vec::new())
+        } else if let Ok(p) = Position::from_fen(from) {
+            (p, Vec::new())
+        } else {
+            parse_pgn(from)?
+        };
+        let mut pos = start.clone();
+        state.game.record.clear();
+        for m in moves {
+            state.game.record.push(pos.san(&m));
+            pos = pos.play(&m);
+        }
+        state.game.setup = start.to_fen();
+        state.game.position = pos.to_fen();
+        Self::set_turn(state, &pos);
+        Self::outcome(state, &pos);
+        Self::lay_out(state, &pos);
+        Ok(())
+    }
+    fn check_move(
+        &self,
+        state: &State,
+        user: &String,
+        name: &String,
+        to: &Token,
+        choice: &str,
+    ) -> Result<(), String> {
+        state.game.check_turn(user)?;
+        Self::resolve(state, name, to, choice).map(|_| ())
+    }
+    fn check_create(
+        &self,
+        _state: &State,
+        _user: &String,
+        _token: &Token,
+        _layer: &Layer,
+    ) -> Result<(), String> {
+        Err("no new pieces in chess".into())
+    }
+    fn check_change(&self, _state: &State, _user: &String, _name: &String) -> Result<(), String> {
+        Err("pieces only change by moving them".into())
+    }
+    fn apply_move(
+        &mut self,
+        state: &mut State,
+        _user: &String,
+        name: &String,
+        to: &Token,
+        choice: &str,
+    ) {
+        let Ok((pos, m)) = Self::resolve(state, name, to, choice) else {
+            return;
+        };
+        if let Some(c) = pos.captured(&m)
+            && let Some(taken) = Self::token_at(state, c, name)
+        {
+            state.destroy_token(&taken);
+        }
+        if let Some((a, b)) = pos.castle_rook(&m)
+            && let Some(rook) = Self::token_at(state, a, name)
+            && let Some(t) = state.tokens.get_mut(&rook)
+        {
+            t.location = square_pos(b);
+        }
+        if let Some(t) = state.tokens.get_mut(name) {
+            t.location = square_pos(m.to);
+            if let Some(k) = m.promotion {
+                t.display_name = k.letter().to_string();
+            }
+        }
+        state.game.record.push(pos.san(&m));
+        let next = pos.play(&m);
+        state.game.position = next.to_fen();
+        Self::set_turn(state, &next);
+    }
+    fn after_move(&mut self, state: &mut State, _user: &String) {
+        if let Ok(pos) = Self::position(state) {
+            Self::outcome(state, &pos);
+        }
+    }
+    fn allows_undo(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+pub fn chess_rules_test() -> Result<(), String> {
+    // fool's mate
+    let (_, moves) = parse_pgn("1. f3 e5 2. g4 Qh4# 0-1")?;
+    let mut pos = Position::start();
+    let mut sans = Vec::new();
+    for m in &moves {
+        sans.push(pos.san(m));
+        pos = pos.play(m);
+    }
+    assert_eq!(sans, ["f3", "e5", "g4", "Qh4#"]);
+    assert_eq!(pos.status(), Status::Checkmate);
+    // en passant and castling through check
+    let pos = Position::from_fen("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1")?;
+    let ep = pos.parse_san("exd6").ok_or("no en passant")?;
+    assert_eq!(pos.captured(&ep), Some((3, 4)));
+    assert!(pos.parse_san("O-O").is_some() && pos.parse_san("O-O-O").is_some());
+    let pos = Position::from_fen("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1")?;
+    assert!(pos.parse_san("O-O").is_none());
+    // promotion and stalemate
+    let pos = Position::from_fen("7k/4P3/6K1/8/8/8/8/8 w - - 0 1")?;
+    let m = pos.parse_san("e8=Q+").ok_or("no promotion")?;
+    assert_eq!(pos.play(&m).get((4, 7)).map(|p| p.kind), Some(Kind::Queen));
+    let pos = Position::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1")?;
+    assert_eq!(pos.status(), Status::Stalemate);
+    assert_eq!(Position::start().to_fen(), START_FEN);
+    assert_eq!(parse_square("e4"), Some((4, 3)));
+    assert_eq!(parse_square("à1"), None);
+    assert_eq!(parse_square("E4"), None);
+    // underpromotion picked by the mover
+    let mut state = State::new();
+    let mut chess = Chess;
+    chess.setup(&mut state, "7k/4P3/6K1/8/8/8/8/8 w - - 0 1")?;
+    let pawn = Chess::token_at(&state, (4, 6), &String::new()).ok_or("no pawn")?;
+    let to = Token {
+        location: square_pos((4, 7)),
+        ..state.tokens[&pawn].clone()
+    };
+    crate::rules::try_move(&mut chess, &mut state, &String::new(), &pawn, &to, "N")?;
+    assert_eq!(state.tokens[&pawn].display_name, "N");
+    assert_eq!(state.game.record, ["e8=N"]);
+    Ok(())
+}
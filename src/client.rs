@@ -1,17 +1,43 @@
 use eframe::egui::{self, Color32, Image, ImageSource, Pos2, Rect, Sense, Stroke, Ui, Vec2};
 use local_ip_address::local_ip;
-use std::{collections::HashSet, net::SocketAddr, net::TcpStream, process::exit, thread::sleep};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    net::SocketAddr,
+    net::TcpStream,
+    process::exit,
+    thread::sleep,
+    time::Instant,
+};
 
 use crate::{
+    aoe::{AreaTemplate, Shape},
+    bundle,
+    cards::{CardPlace, Deck},
+    chess,
     communication::*,
+    database::DataBase,
+    dice::{self, BoardObject},
+    fog::{self, Cell, FogTool},
+    journal::{JournalEntry, Visibility},
+    lighting, persistence, pointers,
+    rules::{self, Outcome},
     server::{EXISTS, SHOULD_DIE},
-    utils::{self, try_read_object, write_object},
+    sheets::{FieldKind, SheetTemplate},
+    throw, throws,
+    utils::{self, AsErr, Exception, try_read_object, write_object},
+    vision::{self, Wall, WallKind},
 };
 #[derive(PartialEq)]
 pub enum Mode {
     MoveAndPlace,
     Measure,
     Draw,
+    Fog,
+    Walls,
+    /// the next click on the board pins a journal entry there
+    Pin,
+    /// drag out an area of effect template
+    Area,
 }
 pub struct Client {
     pub state: State,
@@ -24,6 +50,65 @@ pub struct Client {
     pub owns_server: bool,
     pub working_layer: Layer,
     pub mode: Mode,
+    pub save_error: Option<String>,
+    pub database: DataBase,
+    pub new_alias: String,
+    pub folder: String,
+    /// token open in the inspector as it was when opened, and the copy being edited
+    pub inspecting: Option<(String, Token, Token)>,
+    /// tokens of the working layer that are selected
+    pub selected: HashSet<String>,
+    pub box_start: Option<Pos2>,
+    pub clipboard: Vec<Token>,
+    pub next_request: u64,
+    /// pasted tokens waiting for their id, selected when the ack comes in
+    pub pending_select: HashSet<String>,
+    pub fog_tool: FogTool,
+    /// whether the fog tools hide or reveal
+    pub fog_hide: bool,
+    pub fog_brush: f32,
+    pub fog_start: Option<Pos2>,
+    pub fog_stroke: BTreeSet<Cell>,
+    pub fog_polygon: Vec<Pos2>,
+    pub wall_kind: WallKind,
+    pub wall_start: Option<Pos2>,
+    /// why the server refused the last move or token, shown until dismissed
+    pub rejected: Option<String>,
+    /// rule set picked for the next game
+    pub game_rules: String,
+    /// saved game, like a chess FEN or PGN, the next game starts from
+    pub game_setup: String,
+    /// letter of the piece a pawn moved to the last rank promotes to
+    pub promotion: String,
+    /// folder of card images the gm builds the next deck from
+    pub deck_folder: String,
+    /// comma separated faces for the next custom die
+    pub die_faces: String,
+    /// journal entry open for reading
+    pub reading: Option<String>,
+    /// journal entry the gm is writing, with an empty id for a new one
+    pub entry_edit: Option<(String, JournalEntry)>,
+    /// image file to add to the entry being written
+    pub entry_image: String,
+    pub pin_entry: String,
+    /// the user's notes while they are being typed
+    pub note_draft: Option<String>,
+    /// json file the gm loads the next sheet template from
+    pub template_file: String,
+    /// token whose character sheet is open
+    pub sheet_open: Option<String>,
+    /// a text field of the open sheet while it is being typed, by key
+    pub sheet_draft: Option<(String, String)>,
+    pub area_shape: Shape,
+    pub area_start: Option<Pos2>,
+    /// pings still rippling, by who sent them
+    pub pings: Vec<(String, Pos2, Instant)>,
+    /// where other users' pointers were last seen
+    pub cursors: HashMap<String, (Pos2, Instant)>,
+    /// whether this user's pointer is shown to the others
+    pub share_cursor: bool,
+    /// last pointer position sent, None when it is off the board
+    pub cursor_sent: Option<(Pos2, Instant)>,
 }
 impl Default for Client {
     fn default() -> Self {
@@ -33,18 +118,66 @@ impl Default for Client {
 
 impl Client {
     pub fn new() -> Self {
-        let addr = get_ip();
+        let database = DataBase::load();
+        if !database.folder.is_empty()
+            && let Err(e) = set_path(&database.folder)
+        {
+            println!("failed to use folder {}:{:#?}", database.folder, e);
+        }
+        let addr = if database.last_address.is_empty() {
+            get_ip() + ":8080"
+        } else {
+            database.last_address.clone()
+        };
         let mut out = Self {
             state: State::new(),
             typed_message: String::new(),
-            ip_address: addr.to_string() + ":8080",
+            ip_address: addr,
             connection: None,
-            username: "root".into(),
+            username: database.user_name.clone(),
             loaded_images: HashSet::new(),
             owns_server: false,
             people: Vec::new(),
             working_layer: Layer::Base,
             mode: Mode::MoveAndPlace,
+            save_error: None,
+            folder: database.folder.clone(),
+            database,
+            new_alias: String::new(),
+            inspecting: None,
+            selected: HashSet::new(),
+            box_start: None,
+            clipboard: Vec::new(),
+            next_request: 0,
+            pending_select: HashSet::new(),
+            fog_tool: FogTool::Rect,
+            fog_hide: false,
+            fog_brush: 30.0,
+            fog_start: None,
+            fog_stroke: BTreeSet::new(),
+            fog_polygon: Vec::new(),
+            wall_kind: WallKind::Wall,
+            wall_start: None,
+            rejected: None,
+            game_rules: rules::FREE_FORM.to_string(),
+            game_setup: String::new(),
+            promotion: "Q".to_string(),
+            deck_folder: String::new(),
+            die_faces: String::new(),
+            reading: None,
+            entry_edit: None,
+            entry_image: String::new(),
+            pin_entry: String::new(),
+            note_draft: None,
+            template_file: String::new(),
+            sheet_open: None,
+            sheet_draft: None,
+            area_shape: Shape::Circle,
+            area_start: None,
+            pings: Vec::new(),
+            cursors: HashMap::new(),
+            share_cursor: false,
+            cursor_sent: None,
         };
         if let Ok(p) = std::fs::read_dir(path()) {
             let s = p
@@ -99,10 +232,7 @@ impl Client {
                                 x: (p.x as i32 / 20 * 20) as f32,
                                 y: (p.y as i32 / 20 * 20) as f32,
                             };
-                            let count = self.state.tokens.len()
-                                + self.state.map.len()
-                                + self.state.gm.len();
-                            let tname = format!("{:#?}_{:#?}", self.username, count);
+                            let tname = self.new_request_id();
                             let fname = format!("file://{}{}", path, name);
                             if should_log {
                                 println!("{:#?}, {:#?}", p2, fname);
@@ -114,8 +244,7 @@ impl Client {
                                     token: Token {
                                         location: p2,
                                         image: name.clone(),
-                                        scale: 1,
-                                        display_name: String::new(),
+                                        ..Default::default()
                                     },
                                     layer: self.working_layer.clone(),
                                 },
@@ -133,99 +262,1902 @@ impl Client {
                                     self.loaded_images.insert(name);
                                     let _ = write_object(obj, &ev0);
                                 }
-                                let _ = write_object(obj, &ev);
+                                let _ = write_object(obj, &ev);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_layer(
+        mutable: bool,
+        ui: &mut Ui,
+        values: &mut std::collections::HashMap<String, Token>,
+        maxd: f32,
+        connection: &mut Option<TcpStream>,
+        username: String,
+        selected: &mut HashSet<String>,
+        choice: &str,
+    ) -> Option<String> {
+        let mut inspect = None;
+        let mut group_delta = None;
+        let mut group_stopped = false;
+        let mut dragged_name = None;
+        for name in State::draw_order(values) {
+            let Some(token) = values.get_mut(&name) else {
+                continue;
+            };
+            let file = if token.image.is_empty() {
+                None
+            } else if let Some(f) = asset_file(&token.image) {
+                Some(f)
+            } else {
+                println!("{:#?}: missing", token.image);
+                continue;
+            };
+            let changed = false;
+            let mut counter = None;
+            let is_selected = mutable && selected.contains(&name);
+            let ar = if mutable {
+                egui::Area::new(name.clone().into())
+                    .order(egui::Order::Background)
+                    .current_pos(Pos2::new(token.location.x, token.location.y))
+                    .show(ui.ctx(), |ui| {
+                        let r = Self::token_body(ui, token, file.as_deref());
+                        if is_selected {
+                            ui.painter().rect_stroke(
+                                r.rect,
+                                0.0,
+                                Stroke::new(2.0, Color32::LIGHT_BLUE),
+                                egui::StrokeKind::Outside,
+                            );
+                        }
+                        Self::draw_token_status(ui, token);
+                        if let BoardObject::Counter { .. } = token.object {
+                            ui.horizontal(|ui| {
+                                if ui.small_button("-").clicked() {
+                                    counter = Some(-1);
+                                }
+                                if ui.small_button("+").clicked() {
+                                    counter = Some(1);
+                                }
+                            });
+                        }
+                    })
+            } else {
+                egui::Area::new(name.clone().into())
+                    .order(egui::Order::Background)
+                    .current_pos(Pos2::new(token.location.x, token.location.y))
+                    .sense(Sense::empty())
+                    .show(ui.ctx(), |ui| {
+                        Self::token_body(ui, token, file.as_deref());
+                        Self::draw_token_status(ui, token);
+                    })
+            };
+            ui.ctx().move_to_top(ar.response.layer_id);
+            if ar.response.double_clicked() || ar.response.secondary_clicked() {
+                inspect = Some(name.clone());
+            }
+            let roll = ar.response.clicked() && matches!(token.object, BoardObject::Die { .. });
+            let gadget = match counter {
+                Some(by) => Some(EventData::CounterChanged {
+                    name: name.clone(),
+                    by,
+                }),
+                None if roll => Some(EventData::DieRolled { name: name.clone() }),
+                None => None,
+            };
+            if let Some(data) = gadget
+                && let Some(c) = connection.as_mut()
+            {
+                let _ = write_object(
+                    c,
+                    &Event {
+                        source: username.clone(),
+                        data,
+                    },
+                );
+            }
+            if ar.response.clicked() {
+                if ui.input(|i| i.modifiers.shift) {
+                    if !selected.remove(&name) {
+                        selected.insert(name.clone());
+                    }
+                } else {
+                    selected.clear();
+                    selected.insert(name.clone());
+                }
+            }
+            if ar.response.dragged() {
+                let old = token.location;
+                token.location = Self::clamp_to_board(old + ar.response.drag_delta(), maxd);
+                Self::transform_hotkeys(ui, token);
+                if is_selected {
+                    group_delta = Some(token.location - old);
+                    dragged_name = Some(name.clone());
+                }
+            }
+            if ar.response.drag_stopped() && is_selected {
+                group_stopped = true;
+                dragged_name = Some(name.clone());
+            }
+            if ar.response.drag_stopped() || changed {
+                token.location = Self::snap_to_grid(token.location);
+                if let Some(c) = connection.as_mut() {
+                    write_object(
+                        c,
+                        &Event {
+                            source: username.clone(),
+                            data: EventData::TokenMoved {
+                                name: name.clone(),
+                                to: token.clone(),
+                                time_stamp: 0,
+                                choice: choice.to_string(),
+                            },
+                        },
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        // the rest of a selection follows the token being dragged
+        for name in selected.iter() {
+            if Some(name) == dragged_name.as_ref() {
+                continue;
+            }
+            let Some(token) = values.get_mut(name) else {
+                continue;
+            };
+            if let Some(d) = group_delta {
+                token.location = Self::clamp_to_board(token.location + d, maxd);
+            }
+            if group_stopped {
+                token.location = Self::snap_to_grid(token.location);
+                if let Some(c) = connection.as_mut() {
+                    let _ = write_object(
+                        c,
+                        &Event {
+                            source: username.clone(),
+                            data: EventData::TokenMoved {
+                                name: name.clone(),
+                                to: token.clone(),
+                                time_stamp: 0,
+                                choice: choice.to_string(),
+                            },
+                        },
+                    );
+                }
+            }
+        }
+        inspect
+    }
+    /// dragging on an empty part of the board selects every token of the working layer it touches,
+    /// shift adds to the current selection
+    pub fn box_select(&mut self, ui: &mut Ui, board: &egui::Response) {
+        let shift = ui.input(|i| i.modifiers.shift);
+        if board.clicked() && !shift {
+            self.selected.clear();
+        }
+        if board.drag_started() {
+            self.box_start = board.interact_pointer_pos();
+        }
+        let Some(start) = self.box_start else {
+            return;
+        };
+        let end = ui.input(|i| i.pointer.latest_pos()).unwrap_or(start);
+        let r = Rect::from_two_pos(start, end);
+        if board.dragged() {
+            ui.painter().rect(
+                r,
+                0.0,
+                Color32::from_rgba_unmultiplied(100, 150, 255, 40),
+                Stroke::new(1.0, Color32::LIGHT_BLUE),
+                egui::StrokeKind::Inside,
+            );
+        }
+        if board.drag_stopped() {
+            if !shift {
+                self.selected.clear();
+            }
+            let layer = self.working_layer.clone();
+            for (name, t) in self.state.layer_mut(&layer).iter() {
+                if r.intersects(Rect::from_min_size(t.location, t.size())) {
+                    self.selected.insert(name.clone());
+                }
+            }
+            self.box_start = None;
+        }
+    }
+    /// delete removes the selection, ctrl+c/ctrl+v copy and paste it and ctrl+d duplicates it.
+    /// ctrl+z/ctrl+y undo and redo, holding alt as the gm undoes anyone's edits
+    pub fn selection_hotkeys(&mut self, ui: &mut Ui) {
+        let layer = self.working_layer.clone();
+        let tokens = self.state.layer_mut(&layer);
+        self.selected.retain(|n| tokens.contains_key(n));
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        let (undo, redo) = ui.input(|i| {
+            (
+                i.modifiers.command && i.key_pressed(egui::Key::Z),
+                i.modifiers.command && i.key_pressed(egui::Key::Y),
+            )
+        });
+        let everyone = self.owns_server && ui.input(|i| i.modifiers.alt);
+        if undo {
+            self.send(EventData::Undo { everyone });
+        }
+        if redo {
+            self.send(EventData::Redo { everyone });
+        }
+        if self.mode != Mode::MoveAndPlace {
+            return;
+        }
+        let (delete, copy, paste, duplicate) = ui.input(|i| {
+            (
+                i.key_pressed(egui::Key::Delete) || i.key_pressed(egui::Key::Backspace),
+                i.events.iter().any(|e| matches!(e, egui::Event::Copy)),
+                i.events.iter().any(|e| matches!(e, egui::Event::Paste(_))),
+                i.modifiers.command && i.key_pressed(egui::Key::D),
+            )
+        });
+        if delete {
+            self.delete_selected();
+        }
+        if copy {
+            self.copy_selected(ui.ctx());
+        }
+        if paste {
+            self.paste();
+        }
+        if duplicate {
+            self.copy_selected(ui.ctx());
+            self.paste();
+        }
+    }
+    pub fn delete_selected(&mut self) {
+        let layer = self.working_layer.clone();
+        for name in std::mem::take(&mut self.selected) {
+            self.send(EventData::TokenDestroyed {
+                name,
+                layer: layer.clone(),
+            });
+        }
+    }
+    /// copies the selection into the client's clipboard, which survives switching maps.
+    /// the system clipboard gets a json copy too so the paste shortcut fires
+    pub fn copy_selected(&mut self, ctx: &egui::Context) {
+        let layer = self.working_layer.clone();
+        let tokens = self.state.layer_mut(&layer);
+        self.clipboard = State::draw_order(tokens)
+            .into_iter()
+            .filter(|n| self.selected.contains(n))
+            .map(|n| tokens[&n].clone())
+            .collect();
+        if let Ok(s) = serde_json::to_string(&self.clipboard) {
+            ctx.copy_text(s);
+        }
+    }
+    /// creates the clipboard's tokens in the working layer one cell down and right of where they
+    /// were copied, keeping their offsets to each other, and selects them once the server acks
+    pub fn paste(&mut self) {
+        let mut created = Vec::new();
+        for mut token in self.clipboard.clone() {
+            token.location = Self::clamp_to_board(token.location + Vec2::splat(20.0), 860.0);
+            let request = self.new_request_id();
+            self.pending_select.insert(request.clone());
+            self.upload_image(&token.image);
+            created.push((request, token));
+        }
+        self.clipboard = created.iter().map(|(_, t)| t.clone()).collect();
+        self.selected.clear();
+        for (name, token) in created {
+            self.send(EventData::TokenCreated {
+                name,
+                token,
+                layer: self.working_layer.clone(),
+            });
+        }
+    }
+    /// id for a TokenCreated request, the server answers it with the token's real id
+    pub fn new_request_id(&mut self) -> String {
+        self.next_request += 1;
+        format!("{}:{}", self.username, self.next_request)
+    }
+    /// sends an image to the server the first time a token uses it
+    pub fn upload_image(&mut self, name: &String) {
+        if self.connection.is_none() || self.loaded_images.contains(name) {
+            return;
+        }
+        let Some(file) = asset_file(name) else {
+            return;
+        };
+        if let Ok(image) = std::fs::read(file) {
+            self.loaded_images.insert(name.clone());
+            self.send(EventData::ImageUpload {
+                name: name.clone(),
+                image,
+            });
+        }
+    }
+    /// gm fog tools: drag a rectangle, paint with the brush, or click polygon corners and
+    /// double click or press enter to close it. escape drops the polygon
+    pub fn fog_input(&mut self, ui: &mut Ui, board: &egui::Response) {
+        let pointer = ui.input(|i| i.pointer.latest_pos());
+        let mut done = Vec::new();
+        match self.fog_tool {
+            FogTool::Rect => {
+                if board.drag_started() {
+                    self.fog_start = board.interact_pointer_pos();
+                }
+                if let Some(start) = self.fog_start {
+                    let r = Rect::from_two_pos(start, pointer.unwrap_or(start));
+                    for c in fog::cells_in_rect(r) {
+                        ui.painter().rect_stroke(
+                            fog::cell_rect(c),
+                            0.0,
+                            Stroke::new(1.0, Color32::LIGHT_BLUE),
+                            egui::StrokeKind::Inside,
+                        );
+                    }
+                    if board.drag_stopped() {
+                        done = fog::cells_in_rect(r);
+                        self.fog_start = None;
+                    }
+                }
+            }
+            FogTool::Brush => {
+                if board.dragged()
+                    && let Some(p) = pointer
+                {
+                    self.fog_stroke
+                        .extend(fog::cells_in_circle(p, self.fog_brush));
+                }
+                if let Some(p) = pointer
+                    && board.hovered()
+                {
+                    ui.painter().circle_stroke(
+                        p,
+                        self.fog_brush,
+                        Stroke::new(1.0, Color32::LIGHT_BLUE),
+                    );
+                }
+                for c in &self.fog_stroke {
+                    ui.painter().rect_filled(
+                        fog::cell_rect(*c),
+                        0.0,
+                        Color32::from_rgba_unmultiplied(100, 150, 255, 60),
+                    );
+                }
+                if board.drag_stopped() {
+                    done = std::mem::take(&mut self.fog_stroke).into_iter().collect();
+                }
+            }
+            FogTool::Polygon => {
+                if board.clicked()
+                    && let Some(p) = board.interact_pointer_pos()
+                {
+                    self.fog_polygon.push(p);
+                }
+                let (close, cancel) = ui.input(|i| {
+                    (
+                        i.key_pressed(egui::Key::Enter),
+                        i.key_pressed(egui::Key::Escape),
+                    )
+                });
+                if !self.fog_polygon.is_empty() {
+                    let mut points = self.fog_polygon.clone();
+                    points.extend(pointer);
+                    ui.painter().add(egui::Shape::closed_line(
+                        points,
+                        Stroke::new(1.0, Color32::LIGHT_BLUE),
+                    ));
+                }
+                if board.double_clicked() || close {
+                    done = fog::cells_in_polygon(&self.fog_polygon);
+                    self.fog_polygon.clear();
+                }
+                if cancel {
+                    self.fog_polygon.clear();
+                }
+            }
+        }
+        if !done.is_empty() {
+            self.send(EventData::FogChanged {
+                cells: done,
+                hidden: self.fog_hide,
+            });
+        }
+    }
+    /// fog sits above the map and token layers, opaque for players and see-through for the gm.
+    /// with dynamic vision players also get every cell their tokens can't see darkened
+    pub fn draw_fog(&self, ctx: &egui::Context) {
+        let fog = &self.state.fog;
+        let visible = if self.owns_server {
+            None
+        } else {
+            self.state.visible_cells(&self.username)
+        };
+        if !fog.enabled && visible.is_none() {
+            return;
+        }
+        let color = if self.owns_server {
+            Color32::from_black_alpha(120)
+        } else {
+            Color32::from_gray(30)
+        };
+        let ar = egui::Area::new(egui::Id::new("fog_of_war"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN))
+            .interactable(false)
+            .show(ctx, |ui| {
+                for c in fog::all_cells() {
+                    let unseen = visible.as_ref().is_some_and(|v| !v.contains(&c));
+                    if fog.is_hidden(c) || unseen {
+                        ui.painter().rect_filled(fog::cell_rect(c), 0.0, color);
+                    }
+                }
+            });
+        ctx.move_to_top(ar.response.layer_id);
+    }
+    /// light glows and ambient darkness over the tokens. the gm always sees through the dark
+    pub fn draw_lighting(&self, ctx: &egui::Context) {
+        let areas = self.state.lit_areas();
+        if areas.is_empty() && self.state.darkness <= 0.0 {
+            return;
+        }
+        let max_alpha = if self.owns_server { 120.0 } else { 255.0 };
+        let levels = self.state.light_levels();
+        let ar = egui::Area::new(egui::Id::new("lighting"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN))
+            .interactable(false)
+            .show(ctx, |ui| {
+                let painter = ui.painter();
+                painter.add(lighting::darkness_mesh(
+                    self.state.darkness,
+                    &levels,
+                    max_alpha,
+                ));
+                for a in &areas {
+                    painter.add(lighting::glow_mesh(a));
+                }
+            });
+        ctx.move_to_top(ar.response.layer_id);
+    }
+    /// a card image at card size, a blank card when the image is hidden or missing
+    pub fn card_face(ui: &mut Ui, image: &str, sense: Sense) -> egui::Response {
+        let (r, response) = ui.allocate_exact_size(Vec2::new(40.0, 60.0), sense);
+        match asset_file(image).filter(|_| !image.is_empty()) {
+            Some(file) => {
+                Image::new(ImageSource::Uri(("file://".to_string() + &file).into()))
+                    .paint_at(ui, r);
+            }
+            None => {
+                ui.painter().rect_filled(r, 3.0, Color32::from_gray(200));
+            }
+        }
+        ui.painter().rect_stroke(
+            r,
+            3.0,
+            Stroke::new(1.0, Color32::BLACK),
+            egui::StrokeKind::Inside,
+        );
+        response
+    }
+    /// decks with their discard pile next to them. click to draw, drag to move,
+    /// right click to shuffle
+    pub fn draw_decks(&mut self, ctx: &egui::Context) {
+        let mut commands = Vec::new();
+        for (id, deck) in self.state.decks.iter_mut() {
+            let ar = egui::Area::new(egui::Id::new(("deck", id.clone())))
+                .order(egui::Order::Background)
+                .current_pos(deck.location)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        Self::card_face(ui, &deck.back, Sense::hover());
+                        if let Some(top) = deck.discard.last() {
+                            Self::card_face(ui, top, Sense::hover());
+                        }
+                    });
+                    ui.small(format!("{} ({} left)", deck.name, deck.cards.len()));
+                });
+            ctx.move_to_top(ar.response.layer_id);
+            let r = ar.response;
+            if r.dragged() {
+                deck.location += r.drag_delta();
+            }
+            if r.drag_stopped() {
+                deck.location = Self::snap_to_grid(deck.location);
+                commands.push(EventData::DeckMoved {
+                    deck: id.clone(),
+                    to: deck.location,
+                });
+            }
+            if r.clicked() {
+                commands.push(EventData::CardDrawn { deck: id.clone() });
+            }
+            r.context_menu(|ui| {
+                if ui.button("shuffle").clicked() {
+                    commands.push(EventData::DeckShuffled {
+                        deck: id.clone(),
+                        with_discards: false,
+                    });
+                }
+                if ui.button("shuffle the discards back in").clicked() {
+                    commands.push(EventData::DeckShuffled {
+                        deck: id.clone(),
+                        with_discards: true,
+                    });
+                }
+                if self.owns_server && ui.button("remove deck").clicked() {
+                    commands.push(EventData::DeckDestroyed { deck: id.clone() });
+                }
+            });
+        }
+        for c in commands {
+            self.send(c);
+        }
+    }
+    /// the cards in this user's hand. drag one onto the board to play it face up,
+    /// with shift held face down
+    pub fn hand_panel(&mut self, ui: &mut Ui) {
+        let Some(hand) = self.state.hands.get(&self.username) else {
+            return;
+        };
+        if hand.is_empty() {
+            return;
+        }
+        let mut commands = Vec::new();
+        ui.horizontal(|ui| {
+            ui.label("hand:");
+            for (index, card) in hand.iter().enumerate() {
+                let r = Self::card_face(ui, &card.image, Sense::click_and_drag());
+                let beside_deck = self
+                    .state
+                    .decks
+                    .get(&card.deck)
+                    .map(|d| Self::snap_to_grid(d.location + Vec2::new(0.0, 80.0)))
+                    .unwrap_or(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN));
+                if r.drag_stopped()
+                    && let Some(p) = ui.input(|i| i.pointer.latest_pos())
+                    && fog::on_board(fog::cell_of(p))
+                {
+                    commands.push(EventData::CardPlayed {
+                        index,
+                        face_up: !ui.input(|i| i.modifiers.shift),
+                        location: Self::snap_to_grid(p),
+                    });
+                }
+                r.context_menu(|ui| {
+                    for (label, face_up) in [("play face up", true), ("play face down", false)] {
+                        if ui.button(label).clicked() {
+                            commands.push(EventData::CardPlayed {
+                                index,
+                                face_up,
+                                location: beside_deck,
+                            });
+                        }
+                    }
+                    let from = CardPlace::Hand { index };
+                    if ui.button("discard").clicked() {
+                        commands.push(EventData::CardDiscarded { from: from.clone() });
+                    }
+                    if ui.button("return to deck").clicked() {
+                        commands.push(EventData::CardReturned { from });
+                    }
+                });
+            }
+        });
+        for c in commands {
+            self.send(c);
+        }
+    }
+    /// gm: builds a deck from a folder of card images. a file called back.png
+    /// (or .jpg) in it becomes the card back
+    pub fn deck_tools(&mut self, ui: &mut Ui) {
+        ui.label("cards");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.deck_folder)
+                .hint_text("card folder")
+                .desired_width(100.0),
+        );
+        if ui.button("create deck").clicked()
+            && let Err(e) = self.create_deck()
+        {
+            self.save_error = Some(format!("can't make a deck of {}: {}", self.deck_folder, e));
+        }
+    }
+    /// the gm's list of scenes, to look at one privately or move players to it
+    pub fn scene_tools(&mut self, ui: &mut Ui) {
+        ui.label("scenes");
+        let names: Vec<String> = self.state.scenes.stored.keys().cloned().collect();
+        for n in names {
+            let shown = if n.is_empty() { "main" } else { n.as_str() };
+            let players = n == self.state.scenes.players;
+            let label = if players {
+                format!("{} (players)", shown)
+            } else {
+                shown.to_string()
+            };
+            if n == self.state.scene {
+                ui.strong(label);
+            } else {
+                ui.label(label);
+            }
+            ui.horizontal(|ui| {
+                if n != self.state.scene && ui.button("view").clicked() {
+                    self.send(EventData::SceneAssigned {
+                        scene: n.clone(),
+                        users: vec![self.username.clone()],
+                    });
+                }
+                if !players && ui.button("pull players").clicked() {
+                    self.send(EventData::ScenePulled { scene: n.clone() });
+                }
+                ui.menu_button("send", |ui| {
+                    for p in self.people.clone() {
+                        if ui.button(&p).clicked() {
+                            self.send(EventData::SceneAssigned {
+                                scene: n.clone(),
+                                users: vec![p],
+                            });
+                        }
+                    }
+                });
+                if !players && ui.button("remove").clicked() {
+                    self.send(EventData::SceneRemoved { scene: n.clone() });
+                }
+            });
+        }
+        if ui.button("new scene").clicked() {
+            self.send(EventData::SceneAdded {
+                state: Box::new(State::new()),
+            });
+        }
+    }
+    pub fn create_deck(&mut self) -> throws!() {
+        let folder = std::path::PathBuf::from(&self.deck_folder);
+        let name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .as_err()?;
+        let mut files: Vec<String> = std::fs::read_dir(&folder)?
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.ends_with(".png") || n.ends_with(".jpg") || n.ends_with(".jpeg"))
+            .collect();
+        files.sort_unstable();
+        let mut deck = Deck {
+            name: name.clone(),
+            location: Pos2::new(fog::BOARD_MIN + fog::CELL, fog::BOARD_MIN + fog::CELL),
+            ..Default::default()
+        };
+        for f in files {
+            // cards are kept in the session under the deck's name so decks can't clash
+            let image = format!("{}_{}", name, f);
+            std::fs::copy(folder.join(&f), session_path() + &image)?;
+            self.upload_image(&image);
+            if f.starts_with("back.") {
+                deck.back = image;
+            } else {
+                deck.cards.push(image);
+            }
+        }
+        if deck.cards.is_empty() {
+            throw!("there are no card images in it");
+        }
+        self.send(EventData::DeckCreated { deck });
+        Ok(())
+    }
+    /// rings the token whose turn it is
+    pub fn draw_turn_marker(&self, ctx: &egui::Context) {
+        let Some(token) = self
+            .state
+            .initiative
+            .current()
+            .and_then(|c| self.state.get_token(&c.token))
+        else {
+            return;
+        };
+        let rect = Rect::from_min_size(token.location, token.size()).expand(3.0);
+        let ar = egui::Area::new(egui::Id::new("turn_marker"))
+            .order(egui::Order::Background)
+            .fixed_pos(rect.min)
+            .interactable(false)
+            .show(ctx, |ui| {
+                ui.painter().rect_stroke(
+                    rect,
+                    3.0,
+                    Stroke::new(3.0, Color32::GOLD),
+                    egui::StrokeKind::Outside,
+                );
+            });
+        ctx.move_to_top(ar.response.layer_id);
+    }
+    /// turn order beside the chat. anyone can add their own tokens and roll for them,
+    /// sorting and clearing is up to the gm
+    pub fn initiative_panel(&mut self, ui: &mut Ui) {
+        let gm = self.owns_server;
+        let mut commands = Vec::new();
+        ui.group(|ui| {
+            ui.set_min_width(180.0);
+            ui.vertical(|ui| {
+                ui.label(format!("initiative, round {}", self.state.initiative.round));
+                let turn = self.state.initiative.turn;
+                for (i, c) in self.state.initiative.order.iter().enumerate() {
+                    let label = self
+                        .state
+                        .get_token(&c.token)
+                        .filter(|t| !t.display_name.is_empty())
+                        .map(|t| t.display_name.clone())
+                        .unwrap_or_else(|| c.token.clone());
+                    let mine = gm
+                        || self
+                            .state
+                            .get_token(&c.token)
+                            .is_some_and(|t| t.owner == self.username);
+                    ui.horizontal(|ui| {
+                        let text = egui::RichText::new(label);
+                        if i == turn {
+                            ui.label(text.strong().color(Color32::GOLD));
+                        } else {
+                            ui.label(text);
+                        }
+                        let mut value = c.initiative;
+                        let drag = ui.add_enabled(mine, egui::DragValue::new(&mut value));
+                        if drag.changed() {
+                            commands.push(EventData::InitiativeSet {
+                                token: c.token.clone(),
+                                initiative: Some(value),
+                            });
+                        }
+                        if mine && ui.small_button("🎲").on_hover_text("roll d20").clicked() {
+                            commands.push(EventData::InitiativeSet {
+                                token: c.token.clone(),
+                                initiative: None,
+                            });
+                        }
+                        if mine && ui.small_button("x").clicked() {
+                            commands.push(EventData::InitiativeRemoved {
+                                token: c.token.clone(),
+                            });
+                        }
+                    });
+                }
+                if ui.button("add selected").clicked() {
+                    for token in &self.selected {
+                        commands.push(EventData::InitiativeAdded {
+                            token: token.clone(),
+                        });
+                    }
+                }
+                let my_turn = gm
+                    || self
+                        .state
+                        .initiative
+                        .current()
+                        .and_then(|c| self.state.get_token(&c.token))
+                        .is_some_and(|t| t.owner == self.username);
+                if ui
+                    .add_enabled(my_turn, egui::Button::new("end turn"))
+                    .clicked()
+                {
+                    commands.push(EventData::TurnEnded);
+                }
+                if gm {
+                    ui.horizontal(|ui| {
+                        if ui.button("sort").clicked() {
+                            commands.push(EventData::InitiativeSorted);
+                        }
+                        if ui.button("clear").clicked() {
+                            commands.push(EventData::InitiativeCleared);
+                        }
+                    });
+                }
+            });
+        });
+        for c in commands {
+            self.send(c);
+        }
+    }
+    /// walls, doors and windows, drawn above the tokens and below the fog
+    pub fn draw_walls(&self, ctx: &egui::Context) {
+        if self.state.walls.is_empty() && self.wall_start.is_none() {
+            return;
+        }
+        let ar = egui::Area::new(egui::Id::new("walls"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN))
+            .interactable(false)
+            .show(ctx, |ui| {
+                for w in self.state.walls.values() {
+                    Self::draw_wall(ui.painter(), w);
+                }
+                if let Some(start) = self.wall_start
+                    && let Some(p) = ctx.pointer_latest_pos()
+                {
+                    let w = Wall {
+                        a: start,
+                        b: vision::snap_to_corner(p),
+                        kind: self.wall_kind,
+                        open: false,
+                    };
+                    Self::draw_wall(ui.painter(), &w);
+                }
+            });
+        ctx.move_to_top(ar.response.layer_id);
+    }
+    pub fn draw_wall(p: &egui::Painter, w: &Wall) {
+        let (color, width) = match w.kind {
+            WallKind::Wall => (Color32::BLACK, 4.0),
+            WallKind::Door => (Color32::from_rgb(140, 80, 30), 4.0),
+            WallKind::Window => (Color32::LIGHT_BLUE, 3.0),
+        };
+        if w.kind == WallKind::Door && w.open {
+            p.add(egui::Shape::dashed_line(
+                &[w.a, w.b],
+                Stroke::new(2.0, color),
+                4.0,
+                4.0,
+            ));
+        } else {
+            p.line_segment([w.a, w.b], Stroke::new(width, color));
+        }
+        if w.kind == WallKind::Door {
+            p.circle_filled(w.midpoint(), 4.0, color);
+        }
+    }
+    /// gm wall tool: drag between grid corners to build, right click a wall to remove it
+    pub fn wall_input(&mut self, ui: &mut Ui, board: &egui::Response) {
+        if board.drag_started() {
+            self.wall_start = board.interact_pointer_pos().map(vision::snap_to_corner);
+        }
+        if board.drag_stopped()
+            && let Some(a) = self.wall_start.take()
+            && let Some(p) = ui.input(|i| i.pointer.latest_pos())
+        {
+            let b = vision::snap_to_corner(p);
+            if a != b {
+                self.send(EventData::WallCreated {
+                    wall: Wall {
+                        a,
+                        b,
+                        kind: self.wall_kind,
+                        open: false,
+                    },
+                });
+            }
+        }
+        if board.secondary_clicked()
+            && let Some(p) = board.interact_pointer_pos()
+            && let Some(id) = self.wall_near(p, 6.0)
+        {
+            self.send(EventData::WallDestroyed { id });
+        }
+    }
+    pub fn wall_near(&self, p: Pos2, max: f32) -> Option<String> {
+        self.state
+            .walls
+            .iter()
+            .map(|(id, w)| (id, vision::distance_to_segment(p, w.a, w.b)))
+            .filter(|(_, d)| *d <= max)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id.clone())
+    }
+    /// clicking the handle in the middle of a door opens or closes it, returns whether one was hit
+    pub fn door_input(&mut self, board: &egui::Response) -> bool {
+        if !board.clicked() {
+            return false;
+        }
+        let Some(p) = board.interact_pointer_pos() else {
+            return false;
+        };
+        let door = self
+            .state
+            .walls
+            .iter()
+            .find(|(_, w)| w.kind == WallKind::Door && w.midpoint().distance(p) <= 8.0)
+            .map(|(id, _)| id.clone());
+        if let Some(id) = door {
+            self.send(EventData::DoorToggled { id });
+            return true;
+        }
+        false
+    }
+    /// alt-click pings a spot for everyone, returns whether this click was a ping
+    pub fn ping_input(&mut self, ui: &Ui, board: &egui::Response) -> bool {
+        let alt_click = ui.input(|i| i.modifiers.alt && i.pointer.primary_clicked());
+        let Some(location) = ui.input(|i| i.pointer.interact_pos()) else {
+            return false;
+        };
+        if !alt_click || !board.rect.contains(location) {
+            return false;
+        }
+        self.pings
+            .push((self.username.clone(), location, Instant::now()));
+        self.send(EventData::Ping {
+            from: self.username.clone(),
+            location,
+        });
+        true
+    }
+    /// sends the pointer position when it is shared, no faster than the server passes it on
+    pub fn cursor_input(&mut self, ui: &Ui, board: &egui::Response) {
+        if !self.share_cursor {
+            return;
+        }
+        let now = Instant::now();
+        let location = ui
+            .input(|i| i.pointer.latest_pos())
+            .filter(|p| board.rect.contains(*p));
+        let due = match (self.cursor_sent, location) {
+            (None, None) => false,
+            (Some((last, t)), Some(p)) => {
+                last != p && now.duration_since(t) >= pointers::CURSOR_INTERVAL
+            }
+            _ => true,
+        };
+        if due {
+            self.cursor_sent = location.map(|p| (p, now));
+            self.send(EventData::CursorMoved {
+                from: self.username.clone(),
+                location,
+            });
+        }
+    }
+    /// ripples where people pinged and the other users' cursors with their names
+    pub fn draw_pointers(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        self.pings
+            .retain(|(_, _, t)| now.duration_since(*t) < pointers::PING_LIFETIME);
+        self.cursors
+            .retain(|_, (_, t)| now.duration_since(*t) < pointers::CURSOR_LIFETIME);
+        if self.pings.is_empty() && self.cursors.is_empty() {
+            return;
+        }
+        let ar = egui::Area::new(egui::Id::new("pointers"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN))
+            .interactable(false)
+            .show(ctx, |ui| {
+                let painter = ui.painter();
+                let font = egui::FontId::proportional(12.0);
+                for (from, p, t) in &self.pings {
+                    let age = now.duration_since(*t).as_secs_f32()
+                        / pointers::PING_LIFETIME.as_secs_f32();
+                    // two rings spreading out and fading, half a lifetime apart
+                    for ring in [age, age - 0.5] {
+                        if (0.0..1.0).contains(&ring) {
+                            let alpha = ((1.0 - ring) * 255.0) as u8;
+                            let color = Color32::from_rgba_unmultiplied(255, 200, 0, alpha);
+                            painter.circle_stroke(*p, 4.0 + ring * 40.0, Stroke::new(3.0, color));
+                        }
+                    }
+                    painter.text(
+                        *p + Vec2::new(0.0, -10.0),
+                        egui::Align2::CENTER_BOTTOM,
+                        from,
+                        font.clone(),
+                        Color32::from_rgb(200, 140, 0),
+                    );
+                }
+                for (from, (p, _)) in &self.cursors {
+                    painter.add(egui::Shape::convex_polygon(
+                        vec![*p, *p + Vec2::new(0.0, 14.0), *p + Vec2::new(10.0, 10.0)],
+                        Color32::from_rgb(60, 120, 220),
+                        Stroke::new(1.0, Color32::WHITE),
+                    ));
+                    painter.text(
+                        *p + Vec2::new(12.0, 12.0),
+                        egui::Align2::LEFT_TOP,
+                        from,
+                        font.clone(),
+                        Color32::from_rgb(60, 120, 220),
+                    );
+                }
+            });
+        ctx.move_to_top(ar.response.layer_id);
+        if !self.pings.is_empty() {
+            ctx.request_repaint();
+        }
+    }
+    /// drag from where an area starts to where it reaches, right click an area's start
+    /// to take it away
+    pub fn area_input(&mut self, ui: &mut Ui, board: &egui::Response) {
+        if board.drag_started() {
+            self.area_start = board.interact_pointer_pos();
+        }
+        if board.drag_stopped()
+            && let Some(start) = self.area_start.take()
+            && let Some(p) = ui.input(|i| i.pointer.latest_pos())
+        {
+            let area = AreaTemplate::new(self.area_shape, start, p);
+            if area.size() > 0 {
+                self.send(EventData::AreaPlaced { area });
+            }
+        }
+        if board.secondary_clicked()
+            && let Some(p) = board.interact_pointer_pos()
+            && let Some(id) = self
+                .state
+                .areas
+                .iter()
+                .find(|(_, a)| a.origin.distance(p) <= 8.0)
+                .map(|(id, _)| id.clone())
+        {
+            self.send(EventData::AreaRemoved { id });
+        }
+    }
+    /// covered cells of every area and of the one being dragged out
+    pub fn draw_areas(&self, ui: &Ui) {
+        let dragging = self
+            .area_start
+            .zip(ui.input(|i| i.pointer.latest_pos()))
+            .map(|(start, p)| AreaTemplate::new(self.area_shape, start, p));
+        if self.state.areas.is_empty() && dragging.is_none() {
+            return;
+        }
+        let ar = egui::Area::new(egui::Id::new("areas"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN))
+            .interactable(false)
+            .show(ui.ctx(), |ui| {
+                let painter = ui.painter();
+                for a in self.state.areas.values().chain(dragging.as_ref()) {
+                    let fill =
+                        Color32::from_rgba_unmultiplied(a.color.r(), a.color.g(), a.color.b(), 70);
+                    for c in a.cells() {
+                        painter.rect_filled(fog::cell_rect(c), 0.0, fill);
+                    }
+                    let stroke = Stroke::new(2.0, a.color);
+                    match a.shape {
+                        Shape::Circle => {
+                            painter.circle_stroke(a.origin, a.size() as f32 * fog::CELL, stroke);
+                        }
+                        _ => {
+                            painter.add(egui::Shape::closed_line(a.outline(), stroke));
+                        }
+                    }
+                    painter.circle_filled(a.origin, 4.0, a.color);
+                }
+            });
+        ui.ctx().move_to_top(ar.response.layer_id);
+    }
+    /// the shape to drag out next and the tokens inside each area
+    pub fn area_tools(&mut self, ui: &mut Ui) {
+        ui.label("areas");
+        ui.horizontal_wrapped(|ui| {
+            for s in Shape::ALL {
+                ui.selectable_value(&mut self.area_shape, s, s.name());
+            }
+        });
+        let mut areas: Vec<(&String, &AreaTemplate)> = self.state.areas.iter().collect();
+        areas.sort_unstable_by_key(|(id, _)| *id);
+        let mut removed = None;
+        for (id, a) in areas {
+            let names: Vec<String> = self
+                .state
+                .tokens_in_area(a)
+                .iter()
+                .map(|n| {
+                    self.state
+                        .get_token(n)
+                        .map(|t| t.display_name.clone())
+                        .filter(|d| !d.is_empty())
+                        .unwrap_or_else(|| n.clone())
+                })
+                .collect();
+            ui.horizontal(|ui| {
+                ui.label(format!("{} {}", a.shape.name(), a.size()));
+                if (self.owns_server || a.owner == self.username) && ui.small_button("x").clicked()
+                {
+                    removed = Some(id.clone());
+                }
+            });
+            if names.is_empty() {
+                ui.weak("nobody inside");
+            } else {
+                ui.label(names.join(", "));
+            }
+        }
+        if let Some(id) = removed {
+            self.send(EventData::AreaRemoved { id });
+        }
+    }
+    /// clicking a pin opens its journal entry, the gm removes one with a right click
+    pub fn pin_input(&mut self, board: &egui::Response) -> bool {
+        let Some(p) = board.interact_pointer_pos() else {
+            return false;
+        };
+        let Some((id, pin)) = self
+            .state
+            .pins
+            .iter()
+            .find(|(_, pin)| pin.location.distance(p) <= 8.0)
+        else {
+            return false;
+        };
+        if board.clicked() {
+            self.reading = Some(pin.entry.clone());
+            return true;
+        }
+        if board.secondary_clicked() && self.owns_server {
+            let id = id.clone();
+            self.send(EventData::PinRemoved { id });
+            return true;
+        }
+        false
+    }
+    pub fn pin_placing(&mut self, board: &egui::Response) {
+        if board.clicked()
+            && let Some(location) = board.interact_pointer_pos()
+        {
+            self.send(EventData::PinPlaced {
+                entry: self.pin_entry.clone(),
+                location,
+            });
+            self.mode = Mode::MoveAndPlace;
+        }
+    }
+    pub fn draw_pins(&self, ctx: &egui::Context) {
+        if self.state.pins.is_empty() {
+            return;
+        }
+        let ar = egui::Area::new(egui::Id::new("pins"))
+            .order(egui::Order::Background)
+            .fixed_pos(Pos2::ZERO)
+            .interactable(false)
+            .show(ctx, |ui| {
+                let painter = ui.painter();
+                for pin in self.state.pins.values() {
+                    painter.circle(
+                        pin.location,
+                        6.0,
+                        Color32::from_rgb(200, 40, 40),
+                        Stroke::new(1.5, Color32::WHITE),
+                    );
+                    if let Some(e) = self.state.journal.get(&pin.entry) {
+                        painter.text(
+                            pin.location + Vec2::new(9.0, 0.0),
+                            egui::Align2::LEFT_CENTER,
+                            &e.title,
+                            egui::FontId::proportional(12.0),
+                            Color32::BLACK,
+                        );
+                    }
+                }
+            });
+        ctx.move_to_top(ar.response.layer_id);
+    }
+    /// journal titles to open, and the user's own notes
+    pub fn journal_panel(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.set_min_width(180.0);
+            ui.vertical(|ui| {
+                ui.label("journal");
+                for (id, e) in &self.state.journal {
+                    let title = if e.title.is_empty() { id } else { &e.title };
+                    if ui.link(title).clicked() {
+                        self.reading = Some(id.clone());
+                    }
+                }
+                if self.owns_server && ui.button("new entry").clicked() {
+                    self.entry_edit = Some((String::new(), JournalEntry::default()));
+                }
+                ui.label("notes");
+                let mut text = match &self.note_draft {
+                    Some(d) => d.clone(),
+                    None => self
+                        .state
+                        .notes
+                        .get(&self.username)
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                let edit = ui.add(
+                    egui::TextEdit::multiline(&mut text)
+                        .desired_rows(4)
+                        .hint_text("only you can read these"),
+                );
+                if edit.changed() {
+                    self.note_draft = Some(text);
+                }
+                // sent once typing is done so the state coming back can't undo keystrokes
+                if edit.lost_focus()
+                    && let Some(text) = self.note_draft.take()
+                {
+                    self.state.notes.insert(self.username.clone(), text.clone());
+                    self.send(EventData::NoteChanged { text });
+                }
+            });
+        });
+    }
+    pub fn journal_reader(&mut self, ctx: &egui::Context) {
+        let Some(id) = self.reading.clone() else {
+            return;
+        };
+        let Some(entry) = self.state.journal.get(&id).cloned() else {
+            self.reading = None;
+            return;
+        };
+        let mut open = true;
+        let mut command = None;
+        egui::Window::new(&entry.title)
+            .id(egui::Id::new("journal_reader"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(&entry.text);
+                for image in &entry.images {
+                    if let Some(file) = asset_file(image) {
+                        ui.add(
+                            Image::new(ImageSource::Uri(("file://".to_string() + &file).into()))
+                                .max_width(300.0),
+                        );
+                    }
+                }
+                if self.owns_server {
+                    ui.horizontal(|ui| {
+                        if ui.button("edit").clicked() {
+                            self.entry_edit = Some((id.clone(), entry.clone()));
+                        }
+                        if ui.button("pin to map").clicked() {
+                            self.pin_entry = id.clone();
+                            self.mode = Mode::Pin;
+                        }
+                        if ui.button("delete").clicked() {
+                            command = Some(EventData::JournalEntryRemoved { id: id.clone() });
+                        }
+                    });
+                }
+            });
+        if let Some(c) = command {
+            self.send(c);
+            open = false;
+        }
+        if !open {
+            self.reading = None;
+        }
+    }
+    /// the gm's editor for a journal entry and who may read it
+    pub fn entry_editor(&mut self, ctx: &egui::Context) {
+        let Some((_, entry)) = self.entry_edit.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut save = false;
+        let mut add_image = false;
+        egui::Window::new("journal entry")
+            .id(egui::Id::new("entry_editor"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.text_edit_singleline(&mut entry.title);
+                ui.text_edit_multiline(&mut entry.text);
+                let mut removed = None;
+                for (i, image) in entry.images.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(image);
+                        if ui.small_button("x").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    entry.images.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.entry_image)
+                            .hint_text("image file")
+                            .desired_width(120.0),
+                    );
+                    add_image = ui.button("add image").clicked();
+                });
+                ui.horizontal(|ui| {
+                    let v = &mut entry.visibility;
+                    if ui
+                        .selectable_label(*v == Visibility::Private, "gm only")
+                        .clicked()
+                    {
+                        *v = Visibility::Private;
+                    }
+                    if ui
+                        .selectable_label(*v == Visibility::Everyone, "everyone")
+                        .clicked()
+                    {
+                        *v = Visibility::Everyone;
+                    }
+                    if ui
+                        .selectable_label(matches!(v, Visibility::Players(_)), "players")
+                        .clicked()
+                    {
+                        *v = Visibility::Players(Vec::new());
+                    }
+                });
+                if let Visibility::Players(players) = &mut entry.visibility {
+                    for p in &self.people {
+                        let mut shown = players.contains(p);
+                        if ui.checkbox(&mut shown, p).changed() {
+                            if shown {
+                                players.push(p.clone());
+                            } else {
+                                players.retain(|q| q != p);
+                            }
+                        }
+                    }
+                }
+                save = ui.button("save").clicked();
+            });
+        if add_image {
+            match self.add_entry_image() {
+                Ok(image) => {
+                    if let Some((_, e)) = self.entry_edit.as_mut() {
+                        e.images.push(image);
+                    }
+                    self.entry_image.clear();
+                }
+                Err(e) => {
+                    self.save_error = Some(format!("can't add {}: {}", self.entry_image, e));
+                }
+            }
+        }
+        if save && let Some((id, entry)) = self.entry_edit.take() {
+            self.send(EventData::JournalEntrySaved { id, entry });
+        } else if !open {
+            self.entry_edit = None;
+        }
+    }
+    /// copies an image into the session and uploads it, returning its name there
+    pub fn add_entry_image(&mut self) -> throws!(String) {
+        let file = std::path::PathBuf::from(&self.entry_image);
+        let name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .as_err()?;
+        std::fs::copy(&file, session_path() + &name)?;
+        self.upload_image(&name);
+        Ok(name)
+    }
+    pub fn wall_tools(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.wall_kind, WallKind::Wall, "wall");
+            ui.selectable_value(&mut self.wall_kind, WallKind::Door, "door");
+            ui.selectable_value(&mut self.wall_kind, WallKind::Window, "window");
+        });
+        let mut enabled = self.state.dynamic_vision;
+        if ui.checkbox(&mut enabled, "line of sight").changed() {
+            self.send(EventData::VisionEnabled { enabled });
+        }
+        let mut darkness = self.state.darkness;
+        if ui
+            .add(egui::Slider::new(&mut darkness, 0.0..=1.0).text("darkness"))
+            .changed()
+        {
+            self.send(EventData::DarknessChanged { darkness });
+        }
+    }
+    pub fn fog_tools(&mut self, ui: &mut Ui) {
+        let mut enabled = self.state.fog.enabled;
+        if ui.checkbox(&mut enabled, "fog of war").changed() {
+            self.send(EventData::FogEnabled { enabled });
+        }
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.fog_hide, false, "reveal");
+            ui.selectable_value(&mut self.fog_hide, true, "hide");
+        });
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.fog_tool, FogTool::Rect, "rect");
+            ui.selectable_value(&mut self.fog_tool, FogTool::Polygon, "polygon");
+            ui.selectable_value(&mut self.fog_tool, FogTool::Brush, "brush");
+        });
+        if self.fog_tool == FogTool::Brush {
+            ui.add(egui::Slider::new(&mut self.fog_brush, 10.0..=100.0).text("size"));
+        }
+        ui.horizontal(|ui| {
+            if ui.button("hide all").clicked() {
+                self.send(EventData::FogChanged {
+                    cells: fog::all_cells(),
+                    hidden: true,
+                });
+            }
+            if ui.button("reveal all").clicked() {
+                self.send(EventData::FogChanged {
+                    cells: fog::all_cells(),
+                    hidden: false,
+                });
+            }
+        });
+    }
+    pub fn clamp_to_board(p: Pos2, maxd: f32) -> Pos2 {
+        Pos2::new(p.x.clamp(100.0, maxd), p.y.clamp(100.0, maxd))
+    }
+    pub fn snap_to_grid(p: Pos2) -> Pos2 {
+        Pos2::new(
+            ((p.x as i32) / 20 * 20) as f32,
+            ((p.y as i32) / 20 * 20) as f32,
+        )
+    }
+    /// the token's image sized, rotated, flipped and tinted as stored in the token
+    pub fn token_image<'a>(token: &Token, file: &str) -> Image<'a> {
+        let (x0, x1) = if token.flip_x { (1.0, 0.0) } else { (0.0, 1.0) };
+        let (y0, y1) = if token.flip_y { (1.0, 0.0) } else { (0.0, 1.0) };
+        Image::new(ImageSource::Uri(("file://".to_string() + file).into()))
+            .fit_to_exact_size(token.size())
+            .uv(Rect::from_min_max(Pos2::new(x0, y0), Pos2::new(x1, y1)))
+            .rotate(token.rotation.to_radians(), Vec2::splat(0.5))
+            .tint(token.tint)
+    }
+    /// the token's image, or for a token without one a square of its tint,
+    /// drawn as a labelled disc when it has a name (like a chess piece).
+    /// dice and counters get their face or value written on top
+    pub fn token_body(ui: &mut Ui, token: &Token, file: Option<&str>) -> egui::Response {
+        let response = match file {
+            Some(file) => ui.add(Self::token_image(token, file)),
+            None => ui.allocate_exact_size(token.size(), Sense::hover()).1,
+        };
+        let r = response.rect;
+        let painter = ui.painter();
+        let text_color = if token.tint.intensity() > 0.5 {
+            Color32::BLACK
+        } else {
+            Color32::WHITE
+        };
+        let size = r.width().min(r.height());
+        // dice and counters show their face or value, on a tile if there is no image
+        if let Some(label) = token.object.label() {
+            if file.is_none() {
+                painter.rect(
+                    r.shrink(1.0),
+                    size * 0.15,
+                    token.tint,
+                    Stroke::new(1.5, text_color),
+                    egui::StrokeKind::Inside,
+                );
+            }
+            painter.text(
+                r.center(),
+                egui::Align2::CENTER_CENTER,
+                label,
+                egui::FontId::proportional(size * 0.45),
+                text_color,
+            );
+        } else if file.is_none() {
+            if token.display_name.is_empty() {
+                painter.rect_filled(r, 0.0, token.tint);
+            } else {
+                let radius = size * 0.4;
+                painter.circle(r.center(), radius, token.tint, Stroke::new(1.5, text_color));
+                painter.text(
+                    r.center(),
+                    egui::Align2::CENTER_CENTER,
+                    &token.display_name,
+                    egui::FontId::proportional(radius * 1.2),
+                    text_color,
+                );
+            }
+        }
+        response
+    }
+    /// while a token is dragged: r/shift+r rotate by 45 degrees, h/v flip,
+    /// left/right change the width and up/down the height
+    pub fn transform_hotkeys(ui: &Ui, token: &mut Token) {
+        ui.input(|i| {
+            if i.key_pressed(egui::Key::R) {
+                token.rotate_by(if i.modifiers.shift { -45.0 } else { 45.0 });
+            }
+            if i.key_pressed(egui::Key::H) {
+                token.flip_x = !token.flip_x;
+            }
+            if i.key_pressed(egui::Key::V) {
+                token.flip_y = !token.flip_y;
+            }
+            if i.key_pressed(egui::Key::ArrowRight) {
+                token.width += 1;
+            }
+            if i.key_pressed(egui::Key::ArrowLeft) {
+                token.width = (token.width - 1).max(1);
+            }
+            if i.key_pressed(egui::Key::ArrowDown) {
+                token.height += 1;
+            }
+            if i.key_pressed(egui::Key::ArrowUp) {
+                token.height = (token.height - 1).max(1);
+            }
+        });
+    }
+    /// hp bar, condition icons and name drawn under a token
+    pub fn draw_token_status(ui: &mut Ui, token: &Token) {
+        if token.max_hp > 0 {
+            let (r, _) = ui.allocate_exact_size(Vec2::new(token.size().x, 3.0), Sense::hover());
+            let frac = (token.hp as f32 / token.max_hp as f32).clamp(0.0, 1.0);
+            let color = if frac > 0.5 {
+                Color32::GREEN
+            } else if frac > 0.25 {
+                Color32::YELLOW
+            } else {
+                Color32::RED
+            };
+            ui.painter().rect_filled(r, 0.0, Color32::DARK_GRAY);
+            ui.painter().rect_filled(
+                Rect::from_min_size(r.min, Vec2::new(r.width() * frac, r.height())),
+                0.0,
+                color,
+            );
+        }
+        if !token.conditions.is_empty() {
+            let icons: String = token.conditions.iter().map(|c| c.icon()).collect();
+            ui.small(icons);
+        }
+        if !token.image.is_empty() {
+            ui.small(&token.display_name);
+        }
+    }
+    /// window for editing the token picked with a double or right click
+    pub fn token_inspector(&mut self, ctx: &egui::Context) {
+        let Some((name, opened, token)) = self.inspecting.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut apply = false;
+        let mut command = None;
+        let mut open_sheet = false;
+        egui::Window::new("token")
+            .id(egui::Id::new("token_inspector"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("name:");
+                    ui.text_edit_singleline(&mut token.display_name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("hp:");
+                    ui.add(egui::DragValue::new(&mut token.hp));
+                    ui.label("/");
+                    ui.add(egui::DragValue::new(&mut token.max_hp).range(0..=i32::MAX));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("owner:");
+                    ui.text_edit_singleline(&mut token.owner);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("vision:");
+                    ui.add(
+                        egui::DragValue::new(&mut token.vision)
+                            .range(0.0..=40.0)
+                            .suffix(" cells"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("light:");
+                    ui.add(
+                        egui::DragValue::new(&mut token.light.bright)
+                            .range(0.0..=40.0)
+                            .prefix("bright "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut token.light.dim)
+                            .range(0.0..=40.0)
+                            .prefix("dim "),
+                    );
+                    ui.color_edit_button_srgba(&mut token.light.color);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("size:");
+                    ui.add(egui::DragValue::new(&mut token.width).range(1..=40));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut token.height).range(1..=40));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("rotation:");
+                    ui.add(
+                        egui::DragValue::new(&mut token.rotation)
+                            .range(0.0..=359.0)
+                            .suffix("°"),
+                    );
+                    if ui.button("⟲").clicked() {
+                        token.rotate_by(-45.0);
+                    }
+                    if ui.button("⟳").clicked() {
+                        token.rotate_by(45.0);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut token.flip_x, "flip horizontal");
+                    ui.checkbox(&mut token.flip_y, "flip vertical");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("tint:");
+                    ui.color_edit_button_srgba(&mut token.tint);
+                });
+                ui.label("conditions:");
+                ui.horizontal_wrapped(|ui| {
+                    for c in Condition::ALL {
+                        let mut on = token.conditions.contains(&c);
+                        if ui
+                            .toggle_value(&mut on, c.icon())
+                            .on_hover_text(format!("{:?}", c))
+                            .changed()
+                        {
+                            if on {
+                                token.conditions.push(c);
+                            } else {
+                                token.conditions.retain(|i| *i != c);
+                            }
+                        }
+                    }
+                });
+                ui.label("fields:");
+                let mut to_remove = None;
+                for (i, (k, v)) in token.fields.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(k).desired_width(80.0));
+                        ui.add(egui::TextEdit::singleline(v).desired_width(120.0));
+                        if ui.small_button("x").clicked() {
+                            to_remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = to_remove {
+                    token.fields.remove(i);
+                }
+                if ui.button("add field").clicked() {
+                    token.fields.push((String::new(), String::new()));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("bring to front").clicked() {
+                        command = Some(EventData::TokenReordered {
+                            name: name.clone(),
+                            order: ZOrder::Front,
+                        });
+                    }
+                    if ui.button("send to back").clicked() {
+                        command = Some(EventData::TokenReordered {
+                            name: name.clone(),
+                            order: ZOrder::Back,
+                        });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("move to layer:");
+                    let layers = [
+                        ("tokens", Layer::Base),
+                        ("map", Layer::Map),
+                        ("gm", Layer::Gm),
+                    ];
+                    // only the gm may hide tokens on the gm layer
+                    let shown = if self.owns_server { 3 } else { 2 };
+                    for (label, layer) in layers.into_iter().take(shown) {
+                        if ui.button(label).clicked() {
+                            command = Some(EventData::TokenLayerChanged {
+                                name: name.clone(),
+                                layer,
+                            });
+                        }
+                    }
+                });
+                if !token.card.is_empty() {
+                    ui.horizontal(|ui| {
+                        ui.label("card:");
+                        if ui.button("flip").clicked() {
+                            command = Some(EventData::CardFlipped { name: name.clone() });
+                        }
+                        if ui.button("take").clicked() {
+                            command = Some(EventData::CardTaken { name: name.clone() });
+                        }
+                        let from = CardPlace::Board { name: name.clone() };
+                        if ui.button("discard").clicked() {
+                            command = Some(EventData::CardDiscarded { from: from.clone() });
+                        }
+                        if ui.button("return to deck").clicked() {
+                            command = Some(EventData::CardReturned { from });
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("sheet:");
+                    let current = self.state.sheets.get(name.as_str()).map(|s| &s.template);
+                    egui::ComboBox::from_id_salt("sheet_template")
+                        .selected_text(current.map(|t| t.as_str()).unwrap_or("none"))
+                        .show_ui(ui, |ui| {
+                            let none = String::new();
+                            for t in std::iter::once(&none).chain(self.state.sheet_templates.keys())
+                            {
+                                let shown = if t.is_empty() { "none" } else { t.as_str() };
+                                if ui.selectable_label(current == Some(t), shown).clicked() {
+                                    command = Some(EventData::SheetAttached {
+                                        token: name.clone(),
+                                        template: t.clone(),
+                                    });
+                                }
+                            }
+                        });
+                    if current.is_some() && ui.button("open").clicked() {
+                        open_sheet = true;
+                    }
+                });
+                if ui.button("apply").clicked() {
+                    apply = true;
+                }
+            });
+        if open_sheet {
+            self.sheet_open = Some(name.clone());
+            self.sheet_draft = None;
+        }
+        if apply && let Some(current) = self.state.get_token(name) {
+            // only what was edited here goes out, the rest stays as the server last sent it
+            let merged = current.merge_edits(opened, token);
+            *opened = token.clone();
+            self.state.update_token(name, &merged);
+            if let Some(con) = self.connection.as_mut() {
+                let _ = write_object(
+                    con,
+                    &Event {
+                        source: self.username.clone(),
+                        data: EventData::TokenUpdated {
+                            name: name.clone(),
+                            token: merged,
+                        },
+                    },
+                );
+            }
+        }
+        if let Some(command) = command {
+            self.send(command);
+        }
+        if !open {
+            self.inspecting = None;
+        }
+    }
+    /// the character sheet of a token, editable by whoever controls the token
+    pub fn sheet_window(&mut self, ctx: &egui::Context) {
+        let Some(name) = self.sheet_open.clone() else {
+            return;
+        };
+        let (Some(sheet), Some(token)) =
+            (self.state.sheets.get(&name), self.state.get_token(&name))
+        else {
+            self.sheet_open = None;
+            return;
+        };
+        let Some(template) = self.state.sheet_templates.get(&sheet.template) else {
+            self.sheet_open = None;
+            return;
+        };
+        let editable = self.owns_server || token.owner == self.username;
+        let title = if token.display_name.is_empty() {
+            format!("{} sheet", sheet.template)
+        } else {
+            token.display_name.clone()
+        };
+        let mut open = true;
+        let mut commands = Vec::new();
+        egui::Window::new(title)
+            .id(egui::Id::new("sheet_window"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("sheet_fields").show(ui, |ui| {
+                    for f in &template.fields {
+                        ui.label(&f.label);
+                        match f.kind {
+                            FieldKind::Number => {
+                                let mut value = sheet.value(template, &f.key).unwrap_or(0);
+                                let drag =
+                                    ui.add_enabled(editable, egui::DragValue::new(&mut value));
+                                if drag.changed() {
+                                    commands.push(EventData::SheetChanged {
+                                        token: name.clone(),
+                                        key: f.key.clone(),
+                                        value: value.to_string(),
+                                    });
+                                }
+                            }
+                            FieldKind::Text => {
+                                let mut text = match &self.sheet_draft {
+                                    Some((key, text)) if *key == f.key => text.clone(),
+                                    _ => sheet.text(f),
+                                };
+                                let edit = ui.add_enabled(
+                                    editable,
+                                    egui::TextEdit::singleline(&mut text).desired_width(120.0),
+                                );
+                                if edit.changed() {
+                                    self.sheet_draft = Some((f.key.clone(), text));
+                                }
+                                if edit.lost_focus()
+                                    && let Some((key, value)) = self.sheet_draft.take()
+                                {
+                                    commands.push(EventData::SheetChanged {
+                                        token: name.clone(),
+                                        key,
+                                        value,
+                                    });
+                                }
                             }
+                            FieldKind::Formula => match sheet.value(template, &f.key) {
+                                Ok(v) => {
+                                    ui.label(v.to_string());
+                                }
+                                Err(e) => {
+                                    ui.label("?").on_hover_text(e);
+                                }
+                            },
                         }
+                        ui.end_row();
                     }
-                }
+                });
+                ui.horizontal_wrapped(|ui| {
+                    for (i, r) in template.rolls.iter().enumerate() {
+                        let button = ui.add_enabled(editable, egui::Button::new(&r.label));
+                        if button.on_hover_text(&r.roll).clicked() {
+                            commands.push(EventData::SheetRolled {
+                                token: name.clone(),
+                                roll: i,
+                            });
+                        }
+                    }
+                });
+            });
+        for c in commands {
+            // show typed values right away, the server's copy follows
+            if let EventData::SheetChanged { token, key, value } = &c {
+                self.state.change_sheet(token, key, value.clone());
             }
-        });
+            self.send(c);
+        }
+        if !open {
+            self.sheet_open = None;
+            self.sheet_draft = None;
+        }
     }
-    pub fn draw_layer(
-        mutable: bool,
-        ui: &mut Ui,
-        values: &mut std::collections::HashMap<String, Token>,
-        maxd: f32,
-        connection: &mut Option<TcpStream>,
-        username: String,
-        layer: Layer,
-    ) {
-        for (name, token) in values {
-            if let Err(e) = std::fs::File::open(path().to_string() + &token.image) {
-                println!("{:#?}:{:#?}", token.image, e);
-                continue;
-            }
-            let img = Image::new(ImageSource::Uri(
-                ("file://".to_string() + path() + &token.image).into(),
-            ));
-            let scale = token.scale as f32;
-            let changed = false;
-            let ar = if mutable {
-                let ar = egui::Area::new(name.clone().into())
-                    .current_pos(Pos2::new(token.location.x, token.location.y))
-                    .show(ui.ctx(), |ui| {
-                        let img2 = img.fit_to_exact_size(Vec2::new(20.0 * scale, 20.0 * scale));
-                        ui.add(img2);
-                        ui.small(&token.display_name);
-                    });
-                ar
-            } else {
-                let ar = egui::Area::new(name.clone().into())
-                    .current_pos(Pos2::new(token.location.x, token.location.y))
-                    .sense(Sense::empty())
-                    .show(ui.ctx(), |ui| {
-                        let img2 = img.fit_to_exact_size(Vec2::new(20.0 * scale, 20.0 * scale));
-                        ui.add(img2);
-                        ui.small(&token.display_name);
-                    });
-                ar
-            };
-            if ar.response.dragged() {
-                let mut r =
-                    Pos2::new(token.location.x, token.location.y) + ar.response.drag_delta();
-                if r.x < 100.0 {
-                    r.x = 100.0;
-                }
-                if r.x > maxd {
-                    r.x = maxd;
-                }
-                if r.y < 100.0 {
-                    r.y = 100.0;
+    /// gm loading of sheet templates from json files
+    pub fn sheet_tools(&mut self, ui: &mut Ui) {
+        ui.label("sheets");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.template_file)
+                .hint_text("template file")
+                .desired_width(100.0),
+        );
+        if ui.button("load template").clicked() {
+            let template = std::fs::read_to_string(&self.template_file)
+                .as_err()
+                .and_then(|t| Ok(serde_json::from_str::<SheetTemplate>(&t)?));
+            match template {
+                Ok(template) if template.name.is_empty() => {
+                    self.save_error = Some(format!("{} has no name", self.template_file));
                 }
-                if r.y > maxd {
-                    r.y = maxd;
+                Ok(template) => {
+                    self.save_error = None;
+                    self.send(EventData::SheetTemplateAdded { template });
                 }
-                token.location = Pos2 { x: r.x, y: r.y };
-            }
-            if ar.response.drag_stopped() || changed {
-                token.location = Pos2 {
-                    x: ((token.location.x as i32) / 20 * 20) as f32,
-                    y: ((token.location.y as i32) / 20 * 20) as f32,
-                };
-                if let Some(c) = connection.as_mut() {
-                    write_object(
-                        c,
-                        &Event {
-                            source: username.clone(),
-                            data: EventData::TokenMoved {
-                                name: name.clone(),
-                                to: token.clone(),
-                                time_stamp: 0,
-                                layer: layer.clone(),
-                            },
-                        },
-                    )
-                    .unwrap();
+                Err(e) => {
+                    self.save_error = Some(format!("can't load {}: {}", self.template_file, e));
                 }
             }
         }
     }
+    /// sends an event to the server if connected
+    pub fn send(&mut self, data: EventData) {
+        if let Some(con) = self.connection.as_mut() {
+            let _ = write_object(
+                con,
+                &Event {
+                    source: self.username.clone(),
+                    data,
+                },
+            );
+        }
+    }
     pub fn draw_map(&mut self, ui: &mut Ui) {
         let name = path().to_string() + "board.png";
         if std::fs::File::open(&name).is_err() {
             return;
         }
         let maxd = 860.0;
+        // board areas sit in the background order so windows always stay above them. the
+        // panel drawing the grid goes under them first, then each area is stacked in draw order
+        ui.ctx().move_to_top(ui.layer_id());
         let p = ui.painter();
         p.rect_filled(
             Rect {
@@ -253,69 +2185,110 @@ impl Client {
                 Stroke::new(1.0, Color32::BLACK),
             );
         }
-        ui.allocate_rect(
+        let board_sense = if matches!(
+            self.mode,
+            Mode::MoveAndPlace | Mode::Fog | Mode::Walls | Mode::Pin | Mode::Area
+        ) {
+            Sense::click_and_drag()
+        } else {
+            Sense::empty()
+        };
+        let board = ui.allocate_rect(
             Rect {
                 min: Pos2::new(100.0, 100.0),
                 max: Pos2::new(maxd, maxd),
             },
-            Sense::empty(),
+            board_sense,
         );
-        Self::draw_layer(
+        self.cursor_input(ui, &board);
+        let pinged = self.ping_input(ui, &board);
+        match self.mode {
+            _ if pinged => {}
+            Mode::Fog => self.fog_input(ui, &board),
+            Mode::Walls => self.wall_input(ui, &board),
+            Mode::Pin => self.pin_placing(&board),
+            Mode::Area => self.area_input(ui, &board),
+            _ => {
+                if !self.door_input(&board) && !self.pin_input(&board) {
+                    self.box_select(ui, &board);
+                }
+            }
+        }
+        self.selection_hotkeys(ui);
+        let mut inspect = Self::draw_layer(
             self.working_layer == Layer::Map && self.mode == Mode::MoveAndPlace,
             ui,
             &mut self.state.map,
             maxd,
             &mut self.connection,
             self.username.clone(),
-            Layer::Map,
+            &mut self.selected,
+            &self.promotion,
         );
         if self.working_layer != Layer::Base {
             ui.scope(|ui| {
                 ui.set_opacity(0.9);
-                Self::draw_layer(
+                inspect = inspect.take().or(Self::draw_layer(
                     self.working_layer == Layer::Base && self.mode == Mode::MoveAndPlace,
                     ui,
-                    &mut self.state.gm,
+                    &mut self.state.tokens,
                     maxd,
                     &mut self.connection,
                     self.username.clone(),
-                    Layer::Base,
-                );
+                    &mut self.selected,
+                    &self.promotion,
+                ));
             });
         } else {
-            Self::draw_layer(
+            inspect = inspect.take().or(Self::draw_layer(
                 self.working_layer == Layer::Base && self.mode == Mode::MoveAndPlace,
                 ui,
                 &mut self.state.tokens,
                 maxd,
                 &mut self.connection,
                 self.username.clone(),
-                Layer::Base,
-            );
+                &mut self.selected,
+                &self.promotion,
+            ));
         }
+        self.draw_decks(ui.ctx());
+        self.draw_lighting(ui.ctx());
+        self.draw_walls(ui.ctx());
+        self.draw_areas(ui);
+        self.draw_turn_marker(ui.ctx());
+        self.draw_fog(ui.ctx());
+        self.draw_pins(ui.ctx());
+        self.draw_pointers(ui.ctx());
         if self.working_layer != Layer::Gm {
             ui.scope(|ui| {
                 //ui.set_opacity(0.5);
-                Self::draw_layer(
+                inspect = inspect.take().or(Self::draw_layer(
                     self.working_layer == Layer::Gm && self.mode == Mode::MoveAndPlace,
                     ui,
                     &mut self.state.gm,
                     maxd,
                     &mut self.connection,
                     self.username.clone(),
-                    Layer::Gm,
-                );
+                    &mut self.selected,
+                    &self.promotion,
+                ));
             });
         } else {
-            Self::draw_layer(
+            inspect = inspect.take().or(Self::draw_layer(
                 self.working_layer == Layer::Gm && self.mode == Mode::MoveAndPlace,
                 ui,
                 &mut self.state.gm,
                 maxd,
                 &mut self.connection,
                 self.username.clone(),
-                Layer::Gm,
-            );
+                &mut self.selected,
+                &self.promotion,
+            ));
+        }
+        if let Some(name) = inspect
+            && let Some(token) = self.state.get_token(&name)
+        {
+            self.inspecting = Some((name, token.clone(), token.clone()));
         }
     }
     pub fn map_controls(&mut self, should_log: bool, ui: &mut Ui) {
@@ -327,9 +2300,42 @@ impl Client {
                     ui.text_edit_singleline(&mut self.state.name)
                 });
                 if ui.button("save").clicked() {
-                    let s = serde_json::to_string_pretty(&self.state).unwrap();
-                    let pth = path().to_string() + &self.state.name + ".bored";
-                    let _ = std::fs::write(pth, s);
+                    if let Some(con) = self.connection.as_mut() {
+                        let _ = write_object(
+                            con,
+                            &Event {
+                                source: self.username.clone(),
+                                data: EventData::SaveState {
+                                    name: self.state.name.clone(),
+                                },
+                            },
+                        );
+                    } else {
+                        let pth = path().to_string() + &self.state.name + ".bored";
+                        if let Err(e) = persistence::save_state(&self.state, &pth, true) {
+                            println!("failed to save {}:{:#?}", pth, e);
+                        }
+                    }
+                }
+                ui.horizontal(|ui| {
+                    ui.label("asset folder:");
+                    ui.text_edit_singleline(&mut self.folder);
+                    if ui.button("use").clicked() {
+                        if let Err(e) = set_path(&self.folder) {
+                            self.save_error = Some(format!("can't use {}: {}", self.folder, e));
+                        } else {
+                            self.database.folder = self.folder.clone();
+                            self.save_database();
+                        }
+                    }
+                });
+                if ui.button("export bundle").clicked() {
+                    let pth = path().to_string() + &self.state.name + bundle::BUNDLE_EXTENSION;
+                    if let Err(e) =
+                        bundle::write_bundle(&self.state, &[&session_path(), path()], &pth)
+                    {
+                        self.save_error = Some(format!("failed to export {}: {}", pth, e));
+                    }
                 }
             });
         });
@@ -349,14 +2355,17 @@ impl Client {
                 if ui.button("connect").clicked() {
                     *should_connect = true;
                 }
+                ui.label("campaign:");
+                ui.add(egui::TextEdit::singleline(&mut self.database.campaign).desired_width(80.0));
                 if ui.button("host own server").clicked() {
                     *should_host = true;
+                    self.save_database();
                 }
             } else {
                 if ui.button("disconnect").clicked() {
                     if self.owns_server {
                         write_object(
-                            &mut self.connection.as_mut().unwrap(),
+                            self.connection.as_mut().unwrap(),
                             &Event {
                                 source: self.username.clone(),
                                 data: EventData::Kill {
@@ -392,33 +2401,71 @@ impl Client {
             if ui.button("enter").clicked() {
                 *username_set = true;
             }
-            if let Some(_) = self.connection.as_ref() {
+            if self.connection.as_ref().is_some() {
                 self.username = old;
             }
         });
+        ui.horizontal(|ui| {
+            ui.label("saved servers:");
+            let mut to_remove = None;
+            for (alias, ip) in self.database.aliases() {
+                if self.connection.is_none() && ui.button(&alias).clicked() {
+                    self.ip_address = ip.to_string() + ":8080";
+                    *should_connect = true;
+                }
+                if ui.small_button("x").clicked() {
+                    to_remove = Some(alias);
+                }
+            }
+            if let Some(alias) = to_remove {
+                self.database.alias_table.remove(&alias);
+                self.save_database();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("save address as:");
+            ui.text_edit_singleline(&mut self.new_alias);
+            if ui.button("add").clicked() && !self.new_alias.is_empty() {
+                let ip = self
+                    .ip_address
+                    .strip_suffix(":8080")
+                    .unwrap_or(&self.ip_address);
+                if let Ok(ip) = ip.parse() {
+                    self.database
+                        .alias_table
+                        .insert(std::mem::take(&mut self.new_alias), ip);
+                    self.save_database();
+                }
+            }
+        });
+    }
+    pub fn save_database(&self) {
+        if let Err(e) = self.database.save() {
+            println!("failed to save settings:{:#?}", e);
+        }
     }
     pub fn event_loop_iter(&mut self, should_log: bool) {
         if let Some(t) = self.connection.as_mut() {
             loop {
                 let tr = try_read_object::<Event>(t, &mut Vec::new());
                 if tr.is_err() {
-                    if let Err(e) = tr {
-                        if let Ok(t) = e.downcast::<std::io::Error>() {
-                            match t.kind() {
-                                std::io::ErrorKind::WouldBlock => {
-                                    break;
-                                }
-                                std::io::ErrorKind::ConnectionReset => {
-                                    break;
-                                }
-                                std::io::ErrorKind::UnexpectedEof => {
-                                    break;
-                                }
-                                _ => {
-                                    println!("disconnected {:#?}", t);
-                                    self.connection = None;
-                                    break;
-                                }
+                    if let Err(e) = tr
+                        && let Ok(t) = e.downcast::<std::io::Error>()
+                    {
+                        match t.kind() {
+                            std::io::ErrorKind::WouldBlock => {
+                                break;
+                            }
+                            std::io::ErrorKind::ConnectionReset => {
+                                break;
+                            }
+                            std::io::ErrorKind::UnexpectedEof => {
+                                break;
+                            }
+                            _ => {
+                                println!("disconnected {:#?}", t);
+                                self.connection = None;
+                                break;
                             }
                         }
                     }
@@ -430,14 +2477,13 @@ impl Client {
                 if let Some(ev) = ev {
                     match ev.data {
                         EventData::SendState { state } => {
-                            self.state = state;
+                            self.state = *state;
                         }
                         EventData::ImageUpload { name, image } => {
                             if should_log {
                                 println!("uploaded:{:#?}", name);
                             }
-                            let _ = std::fs::create_dir("assets");
-                            let e = std::fs::write(path().to_string() + &name, &image);
+                            let e = std::fs::write(session_path() + &name, &image);
                             if let Err(e) = e {
                                 println!("{:#?}", e);
                             }
@@ -447,8 +2493,177 @@ impl Client {
                         EventData::PersonalUpdate { people } => {
                             self.people = people;
                         }
-                        _ => {
-                            todo!()
+                        EventData::Message {
+                            from,
+                            contents,
+                            time_stamp: _,
+                        } => {
+                            self.state.messages.push((from, contents));
+                        }
+                        EventData::Connection { username } => {
+                            if !self.people.contains(&username) {
+                                self.people.push(username);
+                                self.people.sort_unstable();
+                            }
+                        }
+                        EventData::Disconnection { username } => {
+                            self.people.retain(|i| *i != username);
+                        }
+                        EventData::Kill { password: _ } => {
+                            if should_log {
+                                println!("server closed");
+                            }
+                            self.owns_server = false;
+                            self.connection = None;
+                            break;
+                        }
+                        EventData::TokenMoved {
+                            name,
+                            to,
+                            time_stamp: _,
+                            choice: _,
+                        } => {
+                            self.state.update_token(&name, &to);
+                        }
+                        EventData::TokenCreatedAck { request, name } => {
+                            if self.pending_select.remove(&request) {
+                                self.selected.insert(name);
+                            }
+                        }
+                        EventData::TokenUpdated { name, token } => {
+                            self.state.update_token(&name, &token);
+                        }
+                        EventData::TokenCreated { name, token, layer } => {
+                            self.state.create_token(name, token, &layer);
+                        }
+                        EventData::TokenReordered { name, order } => {
+                            self.state.reorder_token(&name, &order);
+                        }
+                        EventData::TokenLayerChanged { name, layer } => {
+                            self.state.change_token_layer(&name, &layer);
+                        }
+                        EventData::TokenDestroyed { name, layer: _ } => {
+                            self.state.destroy_token(&name);
+                        }
+                        EventData::HeartBeat => {}
+                        EventData::SaveState { name: _ } => {}
+                        EventData::FogChanged { cells, hidden } => {
+                            self.state.fog.set(&cells, hidden);
+                        }
+                        EventData::FogEnabled { enabled } => {
+                            self.state.fog.enabled = enabled;
+                        }
+                        EventData::WallCreated { wall: _ } => {}
+                        EventData::WallDestroyed { id } => {
+                            self.state.walls.remove(&id);
+                        }
+                        EventData::DoorToggled { id } => {
+                            if let Some(w) = self.state.walls.get_mut(&id) {
+                                w.open = !w.open;
+                            }
+                        }
+                        EventData::VisionEnabled { enabled } => {
+                            self.state.dynamic_vision = enabled;
+                        }
+                        EventData::DarknessChanged { darkness } => {
+                            self.state.darkness = darkness;
+                        }
+                        EventData::InitiativeAdded { token } => {
+                            self.state.initiative.add(token, 0);
+                        }
+                        EventData::InitiativeRemoved { token } => {
+                            self.state.initiative.remove(&token);
+                        }
+                        EventData::InitiativeSet { token, initiative } => {
+                            if let Some(v) = initiative {
+                                self.state.initiative.set(&token, v);
+                            }
+                        }
+                        EventData::InitiativeSorted => {
+                            self.state.initiative.sort();
+                        }
+                        EventData::InitiativeCleared => {
+                            self.state.initiative.clear();
+                        }
+                        EventData::TurnEnded => {}
+                        EventData::DieRolled { name: _ } => {}
+                        EventData::CounterChanged { name, by } => {
+                            self.state.change_counter(&name, by);
+                        }
+                        EventData::DeckCreated { deck: _ } => {}
+                        EventData::SceneAdded { state: _ } => {}
+                        EventData::ScenePulled { scene: _ } => {}
+                        EventData::AreaPlaced { area: _ } => {}
+                        EventData::Ping { from, location } => {
+                            self.pings.push((from, location, Instant::now()));
+                        }
+                        EventData::CursorMoved { from, location } => match location {
+                            Some(p) => {
+                                self.cursors.insert(from, (p, Instant::now()));
+                            }
+                            None => {
+                                self.cursors.remove(&from);
+                            }
+                        },
+                        EventData::AreaRemoved { id: _ } => {}
+                        EventData::SheetTemplateAdded { template: _ } => {}
+                        EventData::SheetAttached {
+                            token: _,
+                            template: _,
+                        } => {}
+                        EventData::SheetChanged {
+                            token: _,
+                            key: _,
+                            value: _,
+                        } => {}
+                        EventData::SheetRolled { token: _, roll: _ } => {}
+                        EventData::DiceRolled { expression: _ } => {}
+                        EventData::JournalEntrySaved { id: _, entry: _ } => {}
+                        EventData::JournalEntryRemoved { id: _ } => {}
+                        EventData::NoteChanged { text: _ } => {}
+                        EventData::PinPlaced {
+                            entry: _,
+                            location: _,
+                        } => {}
+                        EventData::PinRemoved { id: _ } => {}
+                        EventData::SceneAssigned { scene: _, users: _ } => {}
+                        EventData::SceneRemoved { scene: _ } => {}
+                        EventData::DeckMoved { deck, to } => {
+                            if let Some(d) = self.state.decks.get_mut(&deck) {
+                                d.location = to;
+                            }
+                        }
+                        EventData::DeckDestroyed { deck } => {
+                            self.state.decks.remove(&deck);
+                        }
+                        EventData::DeckShuffled {
+                            deck: _,
+                            with_discards: _,
+                        } => {}
+                        EventData::CardDrawn { deck: _ } => {}
+                        EventData::CardPlayed {
+                            index: _,
+                            face_up: _,
+                            location: _,
+                        } => {}
+                        EventData::CardDiscarded { from: _ } => {}
+                        EventData::CardReturned { from: _ } => {}
+                        EventData::CardFlipped { name: _ } => {}
+                        EventData::CardTaken { name: _ } => {}
+                        EventData::GameStarted {
+                            rules: _,
+                            players: _,
+                            setup: _,
+                        } => {}
+                        EventData::ActionRejected { name: _, reason } => {
+                            self.rejected = Some(reason);
+                        }
+                        EventData::Undo { everyone: _ } => {}
+                        EventData::Redo { everyone: _ } => {}
+                        EventData::Unknown => {
+                            if should_log {
+                                println!("unknown event from {:#?}", ev.source);
+                            }
                         }
                     }
                 } else {
@@ -461,33 +2676,106 @@ impl Client {
     }
     pub fn map_switching(&mut self, ui: &mut Ui) {
         ui.vertical(|ui| {
-            let files = std::fs::read_dir(path()).unwrap();
-            for i in files {
-                if let Ok(p) = i {
-                    let name = p.file_name().to_str().unwrap().to_string();
-                    if let Some(n) = name.strip_suffix(".bored") {
-                        if ui.button(n).clicked() {
-                            let Ok(s) = std::fs::read_to_string(path().to_string() + &name) else {
-                                continue;
-                            };
-                            let res_state: Result<State, _> = serde_json::from_str(&s);
-                            if let Ok(s) = res_state {
-                                if let Some(t) = self.connection.as_mut() {
-                                    let _ = write_object(
-                                        t,
-                                        &Event {
-                                            source: self.username.clone(),
-                                            data: EventData::SendState { state: s },
-                                        },
-                                    );
-                                }
-                            }
+            // only the gm loads maps, the server refuses them from anyone else
+            let gm = self.owns_server;
+            let maps = std::fs::read_dir(path()).into_iter().flatten().flatten();
+            for p in maps.filter(|_| gm) {
+                let name = p.file_name().to_str().unwrap().to_string();
+                let Some(n) = name.strip_suffix(".bored") else {
+                    continue;
+                };
+                // a map can replace the current scene or join the session as another one
+                let (load, add) = ui
+                    .horizontal(|ui| {
+                        let load = ui.button(n).clicked();
+                        let add = ui.button("as scene").clicked();
+                        (load, add)
+                    })
+                    .inner;
+                if !load && !add {
+                    continue;
+                }
+                match persistence::load_state(&(path().to_string() + &name)) {
+                    Ok(mut s) => {
+                        self.save_error = None;
+                        if add {
+                            s.scene = n.to_string();
+                            self.send(EventData::SceneAdded { state: Box::new(s) });
+                        } else {
+                            self.send(EventData::SendState { state: Box::new(s) });
                         }
                     }
+                    Err(e) => {
+                        self.save_error = Some(format!("{}: {}", n, e));
+                    }
+                }
+            }
+            for p in std::fs::read_dir(path()).into_iter().flatten().flatten() {
+                let name = p.file_name().to_str().unwrap().to_string();
+                if let Some(n) = name.strip_suffix(bundle::BUNDLE_EXTENSION)
+                    && ui.button(format!("import {}", n)).clicked()
+                {
+                    self.import_bundle(&(path().to_string() + &name));
                 }
             }
+            if let Some(e) = &self.save_error {
+                ui.colored_label(Color32::RED, e);
+            }
         });
     }
+    /// unpacks a bundle into the asset folder, saves it as a map and loads it on the server
+    pub fn import_bundle(&mut self, file: &str) {
+        let res = std::fs::read(file)
+            .as_err()
+            .and_then(|b| bundle::read_bundle(&b))
+            .and_then(|b| bundle::import_bundle(b, path()));
+        let state = match res {
+            Ok(s) => s,
+            Err(e) => {
+                self.save_error = Some(format!("failed to import {}: {}", file, e));
+                return;
+            }
+        };
+        self.save_error = None;
+        // the name comes from the bundle, so it must not lead out of the saves folder
+        if persistence::valid_save_name(&state.name) {
+            let pth = path().to_string() + &state.name + persistence::SAVE_EXTENSION;
+            if let Err(e) = persistence::save_state(&state, &pth, true) {
+                println!("failed to save {}:{:#?}", pth, e);
+            }
+        } else {
+            self.save_error = Some(format!("not saving the map as {:?}", state.name));
+        }
+        if !self.owns_server && self.connection.is_some() {
+            // players keep the imported map for themselves, only the gm loads it for everyone
+            return;
+        }
+        if let Some(t) = self.connection.as_mut() {
+            for name in bundle::referenced_assets(&state) {
+                if let Ok(image) = std::fs::read(path().to_string() + &name) {
+                    self.loaded_images.insert(name.clone());
+                    let _ = write_object(
+                        t,
+                        &Event {
+                            source: self.username.clone(),
+                            data: EventData::ImageUpload { name, image },
+                        },
+                    );
+                }
+            }
+            let _ = write_object(
+                t,
+                &Event {
+                    source: self.username.clone(),
+                    data: EventData::SendState {
+                        state: Box::new(state),
+                    },
+                },
+            );
+        } else {
+            self.state = state;
+        }
+    }
     pub fn user_info(&self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.label("connected users:");
@@ -496,6 +2784,118 @@ impl Client {
             }
         });
     }
+    /// puts dice and counters on the board, in the working layer
+    pub fn table_tools(&mut self, ui: &mut Ui) {
+        if ui
+            .checkbox(&mut self.share_cursor, "share my cursor")
+            .on_hover_text("alt-click pings the board for everyone")
+            .changed()
+            && !self.share_cursor
+            && self.cursor_sent.take().is_some()
+        {
+            self.send(EventData::CursorMoved {
+                from: self.username.clone(),
+                location: None,
+            });
+        }
+        ui.label("dice and counters");
+        let mut object = None;
+        ui.horizontal_wrapped(|ui| {
+            for sides in dice::STANDARD_DICE {
+                if ui.small_button(format!("d{}", sides)).clicked() {
+                    object = Some(BoardObject::die(sides));
+                }
+            }
+        });
+        ui.add(
+            egui::TextEdit::singleline(&mut self.die_faces)
+                .hint_text("faces, comma separated")
+                .desired_width(100.0),
+        );
+        if ui.button("custom die").clicked() {
+            object = BoardObject::custom_die(&self.die_faces);
+        }
+        if ui.button("counter").clicked() {
+            object = Some(BoardObject::Counter { value: 0 });
+        }
+        if let Some(object) = object {
+            let name = self.new_request_id();
+            self.send(EventData::TokenCreated {
+                name,
+                token: Token {
+                    location: Pos2::new(fog::BOARD_MIN, fog::BOARD_MIN),
+                    object,
+                    ..Default::default()
+                },
+                layer: self.working_layer.clone(),
+            });
+        }
+    }
+    /// whose turn it is and how the game ended, and for the gm starting a game
+    pub fn game_info(&mut self, ui: &mut Ui) {
+        let game = &self.state.game;
+        ui.label(if game.rules.is_empty() {
+            rules::FREE_FORM
+        } else {
+            &game.rules
+        });
+        match &game.outcome {
+            Some(Outcome::Win { player }) => {
+                ui.label(format!("{} won", player));
+            }
+            Some(Outcome::Draw) => {
+                ui.label("draw");
+            }
+            None => {
+                if let Some(p) = game.current_player() {
+                    ui.label(format!("{}'s turn", p));
+                }
+            }
+        }
+        if let Some(r) = &self.rejected {
+            ui.colored_label(Color32::RED, r);
+            if ui.small_button("ok").clicked() {
+                self.rejected = None;
+            }
+        }
+        if self.owns_server {
+            egui::ComboBox::from_id_salt("game_rules")
+                .selected_text(&self.game_rules)
+                .show_ui(ui, |ui| {
+                    for r in rules::available_rules() {
+                        ui.selectable_value(&mut self.game_rules, r.to_string(), r);
+                    }
+                });
+            ui.add(
+                egui::TextEdit::multiline(&mut self.game_setup)
+                    .hint_text("saved game to start from")
+                    .desired_rows(2)
+                    .desired_width(100.0),
+            );
+            if ui.button("start game").clicked() {
+                self.send(EventData::GameStarted {
+                    rules: self.game_rules.clone(),
+                    players: self.people.clone(),
+                    setup: self.game_setup.clone(),
+                });
+            }
+        }
+        if self.state.game.rules == chess::CHESS {
+            ui.horizontal(|ui| {
+                ui.label("promote to");
+                for k in chess::Kind::PROMOTIONS {
+                    let letter = k.letter().to_string();
+                    ui.selectable_value(&mut self.promotion, letter.clone(), letter);
+                }
+            });
+            if ui.button("copy FEN").clicked() {
+                ui.ctx().copy_text(self.state.game.position.clone());
+            }
+            if ui.button("copy PGN").clicked() {
+                ui.ctx().copy_text(chess::export_pgn(&self.state.game));
+            }
+        }
+    }
     pub fn tools(&mut self, ui: &mut Ui) {
         ui.vertical(|ui| {
             ui.allocate_rect(
@@ -528,6 +2928,80 @@ impl Client {
                 if ui.button("draw").clicked() {
                     self.mode = Mode::Draw;
                 }
+                if ui.button("area").clicked() {
+                    self.mode = Mode::Area;
+                }
+                if self.owns_server && ui.button("fog").clicked() {
+                    self.mode = Mode::Fog;
+                }
+                if self.owns_server && ui.button("walls").clicked() {
+                    self.mode = Mode::Walls;
+                }
+            });
+            if self.owns_server && self.mode == Mode::Fog {
+                ui.group(|ui| {
+                    self.fog_tools(ui);
+                });
+            }
+            if self.owns_server {
+                ui.group(|ui| {
+                    self.deck_tools(ui);
+                });
+                ui.group(|ui| {
+                    self.sheet_tools(ui);
+                });
+                ui.group(|ui| {
+                    self.scene_tools(ui);
+                });
+            }
+            if self.owns_server && self.mode == Mode::Walls {
+                ui.group(|ui| {
+                    self.wall_tools(ui);
+                });
+            }
+            ui.group(|ui| {
+                ui.label(format!("selected: {}", self.selected.len()));
+                if ui.button("copy").clicked() {
+                    self.copy_selected(ui.ctx());
+                }
+                if ui.button("paste").clicked() {
+                    self.paste();
+                }
+                if ui.button("duplicate").clicked() {
+                    self.copy_selected(ui.ctx());
+                    self.paste();
+                }
+                if ui.button("delete").clicked() {
+                    self.delete_selected();
+                }
+            });
+            if self.mode == Mode::Area || !self.state.areas.is_empty() {
+                ui.group(|ui| {
+                    self.area_tools(ui);
+                });
+            }
+            ui.group(|ui| {
+                self.table_tools(ui);
+            });
+            ui.group(|ui| {
+                self.game_info(ui);
+            });
+            ui.group(|ui| {
+                ui.label("history");
+                if ui.button("undo").clicked() {
+                    self.send(EventData::Undo { everyone: false });
+                }
+                if ui.button("redo").clicked() {
+                    self.send(EventData::Redo { everyone: false });
+                }
+                if self.owns_server {
+                    if ui.button("undo anyone").clicked() {
+                        self.send(EventData::Undo { everyone: true });
+                    }
+                    if ui.button("redo anyone").clicked() {
+                        self.send(EventData::Redo { everyone: true });
+                    }
+                }
             });
         });
     }
@@ -557,9 +3031,16 @@ impl Client {
                         });
                     });
                 });
+                ui.allocate_ui(Vec2::new(200.0, 500.0), |ui| {
+                    ui.vertical(|ui| {
+                        self.initiative_panel(ui);
+                        self.journal_panel(ui);
+                    });
+                });
                 self.draw_images(should_log, ui);
                 self.map_switching(ui);
             });
+            self.hand_panel(ui);
             ui.horizontal(|ui| {
                 ui.label("enter message:");
                 let foc = ui.text_edit_singleline(&mut self.typed_message);
@@ -581,6 +3062,10 @@ impl Client {
             });
             self.map_controls(should_log, ui);
         });
+        self.token_inspector(ui.ctx());
+        self.journal_reader(ui.ctx());
+        self.sheet_window(ui.ctx());
+        self.entry_editor(ui.ctx());
         if should_connect && self.connection.is_none() {
             if should_log {
                 println!("should connect to:{:#?}", self.ip_address);
@@ -593,6 +3078,7 @@ impl Client {
                     .unwrap(),
                 8080,
             );
+            set_session(&self.ip_address);
             if let Ok(mut con) =
                 TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(3))
             {
@@ -605,13 +3091,15 @@ impl Client {
                         },
                     },
                 );
-                if let Err(_) = write_object(
+                if write_object(
                     &mut con,
                     &Event {
                         source: self.username.clone(),
                         data: EventData::HeartBeat,
                     },
-                ) {
+                )
+                .is_err()
+                {
                     self.ip_address = local_ip().unwrap().to_string() + ":8080";
                     if should_log {
                         println!("diconnected");
@@ -619,9 +3107,16 @@ impl Client {
                     self.connection = None;
                 } else {
                     self.connection = Some(con);
+                    self.database.last_address = self.ip_address.clone();
+                    self.database.user_name = self.username.clone();
+                    self.save_database();
                 }
             }
         }
+        if username_set && self.connection.is_none() {
+            self.database.user_name = self.username.clone();
+            self.save_database();
+        }
         if should_send {
             if self.typed_message.starts_with("\\") {
                 let msg = self.typed_message.strip_prefix("\\").unwrap();
@@ -648,8 +3143,16 @@ impl Client {
                 if let Some(con) = self.connection.as_mut() {
                     if self.typed_message == "\\kill" {
                         todo!()
-                    } else if self.typed_message.starts_with("\\roll ") {
-                        todo!()
+                    } else if let Some(expression) = self.typed_message.strip_prefix("\\roll ") {
+                        let _ = write_object(
+                            con,
+                            &Event {
+                                source: self.username.clone(),
+                                data: EventData::DiceRolled {
+                                    expression: expression.to_string(),
+                                },
+                            },
+                        );
                     } else if self.typed_message.starts_with("\\kick ") {
                         todo!()
                     } else if self.typed_message.starts_with("\\ roll_cheat") {
@@ -678,11 +3181,14 @@ impl Client {
         }
         if should_host {
             EXISTS.store(false, std::sync::atomic::Ordering::Release);
-            spawn_host(should_log);
+            spawn_host(should_log, self.database.campaign.clone());
             while !EXISTS.load(std::sync::atomic::Ordering::Acquire)
                 && !SHOULD_DIE.load(std::sync::atomic::Ordering::Acquire)
-            {}
+            {
+                std::hint::spin_loop()
+            }
             if !SHOULD_DIE.load(std::sync::atomic::Ordering::Acquire) {
+                set_session(format!("{}:8080 {}", get_ip(), self.database.campaign).trim());
                 if let Ok(mut con) = TcpStream::connect(get_ip() + ":8080") {
                     sleep(std::time::Duration::from_millis(15));
                     let _ = write_object(
@@ -705,8 +3211,8 @@ impl Client {
         }
     }
 }
-pub fn spawn_host(should_log: bool) {
+pub fn spawn_host(should_log: bool, campaign: String) {
     let _ = std::thread::spawn(move || {
-        crate::server::Server::serve(should_log);
+        crate::server::Server::serve(should_log, &campaign);
     });
 }
@@ -1,13 +1,159 @@
-use std::{collections::HashMap, sync::LazyLock};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{LazyLock, OnceLock, RwLock},
+};
 
-use eframe::egui::Pos2;
+use eframe::egui::{Color32, Pos2, Vec2};
 use serde::{Deserialize, Serialize};
-#[derive(Clone, Debug, Serialize, Deserialize)]
+
+use crate::aoe::AreaTemplate;
+use crate::cards::{CardPlace, Deck, HandCard};
+use crate::dice::BoardObject;
+use crate::fog::{Cell, Fog};
+use crate::initiative::Initiative;
+use crate::journal::{JournalEntry, Pin};
+use crate::lighting::Light;
+use crate::rules::Game;
+use crate::scenes::Scenes;
+use crate::sheets::{Sheet, SheetTemplate};
+use crate::vision::Wall;
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Condition {
+    Blinded,
+    Charmed,
+    Frightened,
+    Invisible,
+    Poisoned,
+    Prone,
+    Restrained,
+    Stunned,
+    Unconscious,
+    Dead,
+}
+impl Condition {
+    pub const ALL: [Condition; 10] = [
+        Condition::Blinded,
+        Condition::Charmed,
+        Condition::Frightened,
+        Condition::Invisible,
+        Condition::Poisoned,
+        Condition::Prone,
+        Condition::Restrained,
+        Condition::Stunned,
+        Condition::Unconscious,
+        Condition::Dead,
+    ];
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Condition::Blinded => "🙈",
+            Condition::Charmed => "💕",
+            Condition::Frightened => "😱",
+            Condition::Invisible => "👻",
+            Condition::Poisoned => "🐍",
+            Condition::Prone => "⬇",
+            Condition::Restrained => "🔗",
+            Condition::Stunned => "💫",
+            Condition::Unconscious => "💤",
+            Condition::Dead => "💀",
+        }
+    }
+}
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Token {
     pub location: Pos2,
-    pub scale: i32,
+    /// size in grid cells
+    pub width: i32,
+    pub height: i32,
+    /// clockwise, in degrees
+    pub rotation: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub tint: Color32,
+    /// draw order within the layer, higher is drawn on top
+    pub z: i64,
     pub image: String,
     pub display_name: String,
+    pub hp: i32,
+    /// 0 means the token doesn't track hit points
+    pub max_hp: i32,
+    pub conditions: Vec<Condition>,
+    /// free form key/value pairs shown in the token inspector
+    pub fields: Vec<(String, String)>,
+    /// user who created the token, players see through the tokens they own
+    pub owner: String,
+    /// sight radius in cells, 0 for none
+    pub vision: f32,
+    pub light: Light,
+    /// deck this token is a card of, empty for other tokens
+    pub card: String,
+    pub object: BoardObject,
+}
+impl Default for Token {
+    fn default() -> Self {
+        Self {
+            location: Pos2::ZERO,
+            width: 1,
+            height: 1,
+            rotation: 0.0,
+            flip_x: false,
+            flip_y: false,
+            tint: Color32::WHITE,
+            z: 0,
+            image: String::new(),
+            display_name: String::new(),
+            hp: 0,
+            max_hp: 0,
+            conditions: Vec::new(),
+            fields: Vec::new(),
+            owner: String::new(),
+            vision: 0.0,
+            light: Light::default(),
+            card: String::new(),
+            object: BoardObject::None,
+        }
+    }
+}
+impl Token {
+    /// on screen size, a grid cell is 20 pixels
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(20.0 * self.width as f32, 20.0 * self.height as f32)
+    }
+    pub fn rotate_by(&mut self, degrees: f32) {
+        self.rotation = (self.rotation + degrees).rem_euclid(360.0);
+    }
+    /// this token with the inspector fields changed between opened and edited, so
+    /// applying an inspector keeps moves and edits others made in the meantime
+    pub fn merge_edits(&self, opened: &Token, edited: &Token) -> Token {
+        let mut out = self.clone();
+        macro_rules! take_changed {
+            ($($field:ident),*) => {
+                $(
+                    if edited.$field != opened.$field {
+                        out.$field = edited.$field.clone();
+                    }
+                )*
+            };
+        }
+        take_changed!(
+            display_name,
+            hp,
+            max_hp,
+            owner,
+            vision,
+            light,
+            width,
+            height,
+            rotation,
+            flip_x,
+            flip_y,
+            tint,
+            conditions,
+            fields
+        );
+        out
+    }
 }
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum Layer {
@@ -15,16 +161,58 @@ pub enum Layer {
     Map,
     Gm,
 }
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ZOrder {
+    Front,
+    Back,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct State {
     pub messages: Vec<(String, String)>,
     pub tokens: HashMap<String, Token>,
     pub map: HashMap<String, Token>,
     pub gm: HashMap<String, Token>,
     pub name: String,
+    /// counter behind the ids the server hands out to new tokens
+    pub next_token_id: u64,
+    pub fog: Fog,
+    pub walls: HashMap<String, Wall>,
+    pub next_wall_id: u64,
+    /// when on, players only see what their tokens can see
+    pub dynamic_vision: bool,
+    /// ambient darkness of the map, 0 is daylight and 1 pitch black
+    pub darkness: f32,
+    pub initiative: Initiative,
+    pub game: Game,
+    pub decks: HashMap<String, Deck>,
+    pub next_deck_id: u64,
+    /// cards held by each user
+    pub hands: HashMap<String, Vec<HandCard>>,
+    /// real images of the cards lying face down on the board, by token
+    pub hidden_cards: HashMap<String, String>,
+    /// name of this scene, empty for the only scene of an older save
+    pub scene: String,
+    pub scenes: Scenes,
+    /// handouts by id, shared by every scene
+    pub journal: BTreeMap<String, JournalEntry>,
+    /// counter behind journal entry and pin ids
+    pub next_entry_id: u64,
+    /// each user's private notes
+    pub notes: HashMap<String, String>,
+    /// journal entries pinned to this scene's board
+    pub pins: HashMap<String, Pin>,
+    /// sheet templates by name, shared by every scene
+    pub sheet_templates: BTreeMap<String, SheetTemplate>,
+    /// character sheets by the token they belong to
+    pub sheets: HashMap<String, Sheet>,
+    /// area of effect templates on the board
+    pub areas: HashMap<String, AreaTemplate>,
+    pub next_area_id: u64,
 }
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
 pub enum EventData {
     Message {
         from: String,
@@ -44,13 +232,37 @@ pub enum EventData {
         name: String,
         to: Token,
         time_stamp: i32,
-        layer: Layer,
+        /// what the mover picked for moves that need a choice, like a piece letter to promote to
+        #[serde(default)]
+        choice: String,
     },
+    /// name is a request id picked by the client, the server gives the token a unique id
+    /// and answers with TokenCreatedAck
     TokenCreated {
         name: String,
         token: Token,
         layer: Layer,
     },
+    /// sent back to the creator of a token with the id the server gave it
+    TokenCreatedAck {
+        request: String,
+        name: String,
+    },
+    /// anything about a token other than its position changed, e.g. from the inspector
+    TokenUpdated {
+        name: String,
+        token: Token,
+    },
+    /// moves a token to the top or bottom of its layer
+    TokenReordered {
+        name: String,
+        order: ZOrder,
+    },
+    /// moves a token into another layer, on top of what is already there
+    TokenLayerChanged {
+        name: String,
+        layer: Layer,
+    },
     TokenDestroyed {
         name: String,
         layer: Layer,
@@ -60,38 +272,409 @@ pub enum EventData {
         image: Vec<u8>,
     },
     SendState {
-        state: State,
+        state: Box<State>,
     },
     PersonalUpdate {
         people: Vec<String>,
     },
     HeartBeat,
+    /// gm only, hides or reveals grid cells
+    FogChanged {
+        cells: Vec<Cell>,
+        hidden: bool,
+    },
+    /// gm only, turns the fog of war on or off
+    FogEnabled {
+        enabled: bool,
+    },
+    /// gm only, the server gives the wall an id
+    WallCreated {
+        wall: Wall,
+    },
+    WallDestroyed {
+        id: String,
+    },
+    /// opens or closes a door, anyone can do this
+    DoorToggled {
+        id: String,
+    },
+    /// gm only, turns line of sight for players on or off
+    VisionEnabled {
+        enabled: bool,
+    },
+    /// gm only, sets the ambient darkness of the map
+    DarknessChanged {
+        darkness: f32,
+    },
+    /// puts a token into the turn order, the gm or the token's owner can do this
+    InitiativeAdded {
+        token: String,
+    },
+    InitiativeRemoved {
+        token: String,
+    },
+    /// sets a token's initiative, None has the server roll a d20
+    InitiativeSet {
+        token: String,
+        initiative: Option<i32>,
+    },
+    /// gm only, orders the tracker by initiative
+    InitiativeSorted,
+    /// gm only, ends the combat
+    InitiativeCleared,
+    /// hands the turn on, only the gm or the owner of the token whose turn it is can do this
+    TurnEnded,
+    /// gm only, starts a game under the named rules with the players in turn order
+    /// setup is an optional saved game the rules understand, like a FEN or PGN for chess
+    GameStarted {
+        rules: String,
+        players: Vec<String>,
+        setup: String,
+    },
+    /// sent back to a client whose move or new token the rules refused
+    ActionRejected {
+        name: String,
+        reason: String,
+    },
+    /// gm only, puts a deck on the board, the server shuffles it
+    DeckCreated {
+        deck: Deck,
+    },
+    DeckMoved {
+        deck: String,
+        to: Pos2,
+    },
+    /// gm only
+    DeckDestroyed {
+        deck: String,
+    },
+    /// shuffles on the server, optionally with the discard pile put back in first
+    DeckShuffled {
+        deck: String,
+        with_discards: bool,
+    },
+    /// the top card goes into the sender's hand
+    CardDrawn {
+        deck: String,
+    },
+    /// puts a card from the sender's hand on the board
+    CardPlayed {
+        index: usize,
+        face_up: bool,
+        location: Pos2,
+    },
+    CardDiscarded {
+        from: CardPlace,
+    },
+    /// puts a card back under its deck
+    CardReturned {
+        from: CardPlace,
+    },
+    /// turns a card on the board over, the gm or the card's owner can do this
+    CardFlipped {
+        name: String,
+    },
+    /// picks a card up off the board into the sender's hand
+    CardTaken {
+        name: String,
+    },
+    /// rolls a die token on the server
+    DieRolled {
+        name: String,
+    },
+    /// adds to a counter token, relative so clicks from several people all count
+    CounterChanged {
+        name: String,
+        by: i64,
+    },
+    /// stores another scene in the session without showing it to anyone, gm only
+    SceneAdded {
+        state: Box<State>,
+    },
+    /// moves everyone to a scene, gm only
+    ScenePulled {
+        scene: String,
+    },
+    /// puts some users on a scene of their own, or back with everyone when it is the
+    /// players' scene. the gm previews a scene by assigning themself
+    SceneAssigned {
+        scene: String,
+        users: Vec<String>,
+    },
+    /// the scene players are on can't be removed
+    SceneRemoved {
+        scene: String,
+    },
+    /// writes a journal entry, a new one when id is empty. gm only
+    JournalEntrySaved {
+        id: String,
+        entry: JournalEntry,
+    },
+    JournalEntryRemoved {
+        id: String,
+    },
+    /// replaces the sender's own notes
+    NoteChanged {
+        text: String,
+    },
+    /// pins a journal entry to a spot on the board, gm only
+    PinPlaced {
+        entry: String,
+        location: Pos2,
+    },
+    PinRemoved {
+        id: String,
+    },
+    /// adds or replaces a sheet template, gm only
+    SheetTemplateAdded {
+        template: SheetTemplate,
+    },
+    /// gives a token a new sheet from a template, or takes its sheet away for an empty one
+    SheetAttached {
+        token: String,
+        template: String,
+    },
+    /// one field of a sheet, so edits from the owner and the gm don't overwrite each other
+    SheetChanged {
+        token: String,
+        key: String,
+        value: String,
+    },
+    /// rolls one of a sheet's roll buttons on the server and posts it to chat
+    SheetRolled {
+        token: String,
+        roll: usize,
+    },
+    /// rolls a dice expression like "2d6 + 3" and posts it to chat
+    DiceRolled {
+        expression: String,
+    },
+    /// puts an area of effect template on the board, owned by the sender
+    AreaPlaced {
+        area: AreaTemplate,
+    },
+    /// the gm or the area's owner can take it away
+    AreaRemoved {
+        id: String,
+    },
+    /// alt-click on the board, everyone sees a ripple there. relayed by the server to the
+    /// others with from filled in, never saved and dropped when sent too fast
+    Ping {
+        from: String,
+        location: Pos2,
+    },
+    /// where a user's pointer is on the board, None once it leaves. relayed like pings
+    CursorMoved {
+        from: String,
+        location: Option<Pos2>,
+    },
+    /// reverts the sender's last board edit, or anyone's when a gm sets everyone
+    Undo {
+        everyone: bool,
+    },
+    Redo {
+        everyone: bool,
+    },
+    /// asks the server to write its state to name.bored
+    SaveState {
+        name: String,
+    },
+    /// any event this build doesn't know about, so newer servers can add variants
+    #[serde(other)]
+    Unknown,
+}
+impl EventData {
+    /// the token an event edits, for the undo history
+    pub fn token_name(&self) -> Option<&String> {
+        match self {
+            EventData::TokenMoved { name, .. }
+            | EventData::TokenUpdated { name, .. }
+            | EventData::TokenReordered { name, .. }
+            | EventData::TokenLayerChanged { name, .. }
+            | EventData::TokenDestroyed { name, .. }
+            | EventData::DieRolled { name }
+            | EventData::CounterChanged { name, .. } => Some(name),
+            _ => None,
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Event {
     pub source: String,
     pub data: EventData,
 }
-pub fn path() -> &'static str {
-    static S: LazyLock<&'static str> = std::sync::LazyLock::new(|| {
-        let dir = std::env::home_dir().unwrap().to_string_lossy().to_string();
-        let d = (dir.clone() + "/boredgames/assets/").leak() as &str;
-        let d0 = dir.clone() + "/boardgames";
-        if !std::fs::exists(&d0).unwrap() {
-            std::fs::create_dir(&d0).unwrap();
+/// env var that overrides where boredgames keeps its data
+pub const DATA_DIR_VAR: &str = "BOREDGAMES_DIR";
+static DATA_ROOT_OVERRIDE: OnceLock<String> = OnceLock::new();
+static PATH_OVERRIDE: RwLock<Option<&'static str>> = RwLock::new(None);
+/// folder of the current session, empty until one is set
+static SESSION: RwLock<String> = RwLock::new(String::new());
+
+fn with_slash(dir: String) -> String {
+    if dir.ends_with('/') || dir.ends_with('\\') {
+        dir
+    } else {
+        dir + "/"
+    }
+}
+/// the per user data folder the platform expects applications to use
+pub fn platform_data_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::env::home_dir().map(|h| h.join("Library").join("Application Support"))
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::home_dir().map(|h| h.join(".local").join("share")))
+    }
+}
+/// sets the data root from the command line, has to happen before anything calls data_root()
+pub fn set_data_root(dir: &str) -> bool {
+    DATA_ROOT_OVERRIDE.set(with_slash(dir.to_string())).is_ok()
+}
+/// root of everything boredgames writes: the command line flag, then $BOREDGAMES_DIR,
+/// then an existing ~/boredgames from older versions, then the platform data folder
+pub fn data_root() -> &'static str {
+    static S: LazyLock<&'static str> = LazyLock::new(|| {
+        let legacy = std::env::home_dir().map(|h| h.join("boredgames"));
+        let candidates = [
+            DATA_ROOT_OVERRIDE.get().map(PathBuf::from),
+            std::env::var_os(DATA_DIR_VAR)
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from),
+            legacy.filter(|l| l.is_dir()),
+            platform_data_dir().map(|d| d.join("boredgames")),
+            Some(std::env::temp_dir().join("boredgames")),
+        ];
+        for dir in candidates.into_iter().flatten() {
+            match std::fs::create_dir_all(&dir) {
+                Ok(()) => return with_slash(dir.to_string_lossy().to_string()).leak(),
+                Err(e) => println!("can't use {:#?} for data:{:#?}", dir, e),
+            }
         }
-        if !std::fs::exists(d).unwrap() {
-            std::fs::create_dir(d).unwrap();
+        "./"
+    });
+    &S
+}
+/// points path() at another asset folder, creating it if needed. an empty folder goes back to the default
+pub fn set_path(folder: &str) -> crate::throws!() {
+    let mut lck = match PATH_OVERRIDE.write() {
+        Ok(t) => t,
+        Err(t) => t.into_inner(),
+    };
+    if folder.is_empty() {
+        *lck = None;
+        return Ok(());
+    }
+    let folder = with_slash(folder.to_string());
+    std::fs::create_dir_all(&folder)?;
+    *lck = Some(folder.leak());
+    Ok(())
+}
+/// the user's own asset library, images here can be placed as tokens and maps are saved here
+pub fn path() -> &'static str {
+    let lck = match PATH_OVERRIDE.read() {
+        Ok(t) => t,
+        Err(t) => t.into_inner(),
+    };
+    if let Some(p) = *lck {
+        return p;
+    }
+    static S: LazyLock<&'static str> = LazyLock::new(|| {
+        let d = data_root().to_string() + "assets/";
+        if let Err(e) = std::fs::create_dir_all(&d) {
+            println!("failed to create {}:{:#?}", d, e);
         }
-        d
+        d.leak()
     });
     &S
 }
+/// folder holding the assets of one server or campaign so they don't mix with other sessions
+pub fn session_dir(session: &str) -> String {
+    let name: String = session
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let d = data_root().to_string() + "sessions/" + &name + "/";
+    if let Err(e) = std::fs::create_dir_all(&d) {
+        println!("failed to create {}:{:#?}", d, e);
+    }
+    d
+}
+/// switches session_path() to the namespace of the given server or campaign
+pub fn set_session(session: &str) {
+    let d = session_dir(session);
+    let mut lck = match SESSION.write() {
+        Ok(t) => t,
+        Err(t) => t.into_inner(),
+    };
+    *lck = d;
+}
+/// where images received from the current server are kept
+pub fn session_path() -> String {
+    let lck = match SESSION.read() {
+        Ok(t) => t,
+        Err(t) => t.into_inner(),
+    };
+    if !lck.is_empty() {
+        return lck.clone();
+    }
+    drop(lck);
+    set_session("local");
+    session_path()
+}
+/// full path of an asset, looked up in the session first and the library second
+pub fn asset_file(name: &str) -> Option<String> {
+    [session_path(), path().to_string()]
+        .into_iter()
+        .map(|d| d + name)
+        .find(|p| std::fs::exists(p).unwrap_or(false))
+}
 pub fn get_ip() -> String {
-    let ip = if let Ok(t) = local_ip_address::local_ip() {
+    if let Ok(t) = local_ip_address::local_ip() {
         t.to_string()
     } else {
         "127.0.0.1".to_string()
+    }
+}
+#[test]
+pub fn unknown_event_test() -> crate::throws!() {
+    let ev: Event = serde_json::from_str(
+        r#"{"source":"_server","data":{"type":"SomethingNew","field":[1,2,3]}}"#,
+    )?;
+    assert!(matches!(ev.data, EventData::Unknown));
+    Ok(())
+}
+#[test]
+pub fn merge_edits_test() {
+    let opened = Token {
+        hp: 10,
+        ..Default::default()
+    };
+    let edited = Token {
+        hp: 7,
+        ..opened.clone()
+    };
+    let current = Token {
+        location: Pos2::new(300.0, 300.0),
+        display_name: "renamed meanwhile".into(),
+        ..opened.clone()
     };
-    ip
+    let merged = current.merge_edits(&opened, &edited);
+    assert_eq!(merged.hp, 7);
+    assert_eq!(merged.location, current.location);
+    assert_eq!(merged.display_name, "renamed meanwhile");
 }
@@ -1,9 +1,62 @@
 use std::{collections::HashMap, net::IpAddr};
 
 use serde::{Deserialize, Serialize};
+
+use crate::communication::data_root;
+use crate::persistence;
+use crate::throws;
+/// per user settings kept between runs, stored as json in the data root
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct DataBase {
     pub user_name: String,
     pub alias_table: HashMap<String, IpAddr>,
+    /// asset folder chosen by the user, empty means the default from path()
     pub folder: String,
+    pub last_address: String,
+    /// campaign the user last hosted, its images and autosave are kept apart from other campaigns
+    pub campaign: String,
+}
+impl Default for DataBase {
+    fn default() -> Self {
+        Self {
+            user_name: "root".into(),
+            alias_table: HashMap::new(),
+            folder: String::new(),
+            last_address: String::new(),
+            campaign: String::new(),
+        }
+    }
+}
+impl DataBase {
+    pub fn config_file() -> String {
+        data_root().to_string() + "settings.json"
+    }
+    /// reads the settings file, a missing or unreadable file gives the defaults
+    pub fn load() -> Self {
+        let Ok(s) = std::fs::read_to_string(Self::config_file()) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&s) {
+            Ok(db) => db,
+            Err(e) => {
+                println!("failed to read settings:{:#?}", e);
+                Self::default()
+            }
+        }
+    }
+    pub fn save(&self) -> throws!() {
+        let s = serde_json::to_string_pretty(self)?;
+        persistence::write_atomic(&Self::config_file(), s.as_bytes())
+    }
+    /// address book entries sorted by name for display
+    pub fn aliases(&self) -> Vec<(String, IpAddr)> {
+        let mut out: Vec<(String, IpAddr)> = self
+            .alias_table
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect();
+        out.sort_unstable();
+        out
+    }
 }
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+use crate::utils::Rng;
+
+/// dice offered in the tools, by number of sides
+pub const STANDARD_DICE: [u32; 6] = [4, 6, 8, 10, 12, 20];
+
+/// what a token is besides its picture
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum BoardObject {
+    #[default]
+    None,
+    /// rolls on the server when clicked, up is the index of the face showing
+    Die { faces: Vec<String>, up: usize },
+    /// a number with +/- buttons, for scores, resources and the like
+    Counter { value: i64 },
+}
+
+impl BoardObject {
+    pub fn die(sides: u32) -> Self {
+        BoardObject::Die {
+            faces: (1..=sides).map(|i| i.to_string()).collect(),
+            up: 0,
+        }
+    }
+    /// a die with faces given as a comma separated list, like "hit, miss, crit"
+    pub fn custom_die(faces: &str) -> Option<Self> {
+        let faces: Vec<String> = faces
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect();
+        (!faces.is_empty()).then_some(BoardObject::Die { faces, up: 0 })
+    }
+    /// the object as it is when first put on the board, a die showing its first face
+    /// and a counter at 0
+    pub fn fresh(&self) -> Self {
+        match self {
+            BoardObject::None => BoardObject::None,
+            BoardObject::Die { faces, up: _ } => BoardObject::Die {
+                faces: faces.clone(),
+                up: 0,
+            },
+            BoardObject::Counter { value: _ } => BoardObject::Counter { value: 0 },
+        }
+    }
+    /// the text shown on the token
+    pub fn label(&self) -> Option<String> {
+        match self {
+            BoardObject::None => None,
+            BoardObject::Die { faces, up } => faces.get(*up).cloned(),
+            BoardObject::Counter { value } => Some(value.to_string()),
+        }
+    }
+}
+
+impl State {
+    /// rolls a die token, returning the face that came up
+    pub fn roll_die(&mut self, name: &String, rng: &mut Rng) -> Option<String> {
+        let mut token = self.get_token(name)?.clone();
+        let BoardObject::Die { faces, up } = &mut token.object else {
+            return None;
+        };
+        if faces.is_empty() {
+            return None;
+        }
+        *up = rng.below(faces.len() as u64) as usize;
+        let face = faces[*up].clone();
+        self.update_token(name, &token);
+        Some(face)
+    }
+    pub fn change_counter(&mut self, name: &String, by: i64) -> bool {
+        let Some(mut token) = self.get_token(name).cloned() else {
+            return false;
+        };
+        let BoardObject::Counter { value } = &mut token.object else {
+            return false;
+        };
+        *value = value.saturating_add(by);
+        self.update_token(name, &token);
+        true
+    }
+}
+
+#[test]
+pub fn dice_and_counters_test() {
+    let mut state = State::new();
+    let mut rng = Rng::new(3);
+    state.tokens.insert(
+        "d".into(),
+        Token {
+            object: BoardObject::custom_die("hit, miss,, crit").unwrap(),
+            ..Default::default()
+        },
+    );
+    state.tokens.insert(
+        "c".into(),
+        Token {
+            object: BoardObject::Counter { value: 2 },
+            ..Default::default()
+        },
+    );
+    for _ in 0..20 {
+        let face = state.roll_die(&"d".into(), &mut rng).unwrap();
+        assert!(["hit", "miss", "crit"].contains(&face.as_str()));
+        assert_eq!(state.tokens["d"].object.label(), Some(face));
+    }
+    assert!(state.roll_die(&"c".into(), &mut rng).is_none());
+    assert!(state.change_counter(&"c".into(), -5));
+    assert_eq!(state.tokens["c"].object.label().as_deref(), Some("-3"));
+    assert_eq!(
+        state.tokens["c"].object.fresh().label().as_deref(),
+        Some("0")
+    );
+    assert_eq!(BoardObject::die(6).label().as_deref(), Some("1"));
+}
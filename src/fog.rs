@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+
+use eframe::egui::{Pos2, Rect, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+
+/// top left corner of the board on screen
+pub const BOARD_MIN: f32 = 100.0;
+/// size of a grid cell in pixels
+pub const CELL: f32 = 20.0;
+/// the board is this many cells wide and high
+pub const BOARD_CELLS: i32 = 38;
+
+pub type Cell = (i32, i32);
+
+/// fog of war, kept as the set of hidden grid cells
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Fog {
+    pub enabled: bool,
+    pub hidden: BTreeSet<Cell>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogTool {
+    Rect,
+    Polygon,
+    Brush,
+}
+
+pub fn cell_of(p: Pos2) -> Cell {
+    (
+        ((p.x - BOARD_MIN) / CELL).floor() as i32,
+        ((p.y - BOARD_MIN) / CELL).floor() as i32,
+    )
+}
+
+pub fn cell_rect(c: Cell) -> Rect {
+    Rect::from_min_size(
+        Pos2::new(BOARD_MIN + c.0 as f32 * CELL, BOARD_MIN + c.1 as f32 * CELL),
+        Vec2::splat(CELL),
+    )
+}
+
+pub fn on_board(c: Cell) -> bool {
+    (0..BOARD_CELLS).contains(&c.0) && (0..BOARD_CELLS).contains(&c.1)
+}
+
+pub fn all_cells() -> Vec<Cell> {
+    (0..BOARD_CELLS)
+        .flat_map(|x| (0..BOARD_CELLS).map(move |y| (x, y)))
+        .collect()
+}
+
+/// the nearest point on the board, points that aren't numbers go to its corner
+pub fn clamp_to_board(p: Pos2) -> Pos2 {
+    let max = BOARD_MIN + BOARD_CELLS as f32 * CELL;
+    let clamp = |v: f32| {
+        if v.is_nan() {
+            BOARD_MIN
+        } else {
+            v.clamp(BOARD_MIN, max)
+        }
+    };
+    Pos2::new(clamp(p.x), clamp(p.y))
+}
+
+/// every cell on the board touched by a screen rectangle
+pub fn cells_in_rect(r: Rect) -> Vec<Cell> {
+    let (x0, y0) = cell_of(r.min);
+    let (x1, y1) = cell_of(r.max - Vec2::splat(0.01));
+    let last = BOARD_CELLS - 1;
+    (x0.max(0)..=x1.min(last))
+        .flat_map(|x| (y0.max(0)..=y1.min(last)).map(move |y| (x, y)))
+        .collect()
+}
+
+/// every cell whose center lies within radius of p
+pub fn cells_in_circle(p: Pos2, radius: f32) -> Vec<Cell> {
+    cells_in_rect(Rect::from_center_size(p, Vec2::splat(radius * 2.0)))
+        .into_iter()
+        .filter(|c| cell_rect(*c).center().distance(p) <= radius)
+        .collect()
+}
+
+/// every cell whose center lies inside the polygon, by the even-odd rule
+pub fn cells_in_polygon(points: &[Pos2]) -> Vec<Cell> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+    let bounds = Rect::from_points(points);
+    cells_in_rect(bounds)
+        .into_iter()
+        .filter(|c| point_in_polygon(cell_rect(*c).center(), points))
+        .collect()
+}
+
+pub fn point_in_polygon(p: Pos2, points: &[Pos2]) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[j]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+impl Fog {
+    pub fn set(&mut self, cells: &[Cell], hidden: bool) {
+        for c in cells {
+            if hidden {
+                self.hidden.insert(*c);
+            } else {
+                self.hidden.remove(c);
+            }
+        }
+    }
+    pub fn is_hidden(&self, c: Cell) -> bool {
+        self.enabled && self.hidden.contains(&c)
+    }
+    /// a token is hidden when fog covers every cell it stands on
+    pub fn hides(&self, token: &Token) -> bool {
+        self.enabled
+            && cells_in_rect(Rect::from_min_size(token.location, token.size()))
+                .into_iter()
+                .all(|c| self.hidden.contains(&c))
+    }
+}
+
+impl State {
+    /// whether for_players keeps the token, players may only act on tokens they were sent
+    pub fn shown_to_players(&self, name: &String) -> bool {
+        self.tokens
+            .get(name)
+            .or_else(|| self.map.get(name))
+            .is_some_and(|t| !self.fog.hides(t))
+    }
+    /// the state as players may see it, without the gm layer, tokens under the fog or
+    /// anything kept about those tokens. walls stay, clients work out line of sight
+    pub fn for_players(&self) -> State {
+        let mut out = self.clone();
+        out.gm.clear();
+        if self.fog.enabled {
+            out.tokens.retain(|_, t| !self.fog.hides(t));
+            out.map.retain(|_, t| !self.fog.hides(t));
+        }
+        let removed: Vec<String> = self
+            .all_tokens_named()
+            .filter(|n| !out.contains_token(n))
+            .cloned()
+            .collect();
+        for name in &removed {
+            out.hidden_cards.remove(name);
+            out.sheets.remove(name);
+            out.initiative.remove(name);
+        }
+        out
+    }
+}
+
+#[test]
+pub fn fog_polygon_test() {
+    let tri = [
+        Pos2::new(100.0, 100.0),
+        Pos2::new(200.0, 100.0),
+        Pos2::new(100.0, 200.0),
+    ];
+    let cells = cells_in_polygon(&tri);
+    assert!(cells.contains(&(0, 0)));
+    assert!(cells.contains(&(3, 0)));
+    assert!(!cells.contains(&(4, 4)));
+    let mut state = State::new();
+    state.fog.enabled = true;
+    state.fog.set(&cells, true);
+    state.tokens.insert(
+        "t".into(),
+        Token {
+            location: Pos2::new(100.0, 100.0),
+            ..Default::default()
+        },
+    );
+    assert!(state.for_players().tokens.is_empty());
+    state.fog.set(&[(0, 0)], false);
+    assert_eq!(state.for_players().tokens.len(), 1);
+}
+
+#[test]
+pub fn players_never_get_hidden_tokens_test() {
+    let mut state = State::new();
+    state.fog.enabled = true;
+    state.fog.set(&[(0, 0)], true);
+    state.gm.insert("secret_door".into(), Token::default());
+    state.tokens.insert(
+        "lurker".into(),
+        Token {
+            location: Pos2::new(100.0, 100.0),
+            ..Default::default()
+        },
+    );
+    state.initiative.add("lurker".into(), 12);
+    state.hidden_cards.insert("lurker".into(), "ace.png".into());
+    state
+        .sheets
+        .insert("lurker".into(), crate::sheets::Sheet::default());
+    let text = serde_json::to_string(&state.for_players()).unwrap();
+    assert!(!text.contains("secret_door"));
+    assert!(!text.contains("lurker"));
+    assert!(!state.shown_to_players(&"secret_door".into()));
+    assert!(!state.shown_to_players(&"lurker".into()));
+    state.fog.set(&[(0, 0)], false);
+    assert!(state.shown_to_players(&"lurker".into()));
+}
@@ -0,0 +1,139 @@
+use crate::communication::*;
+
+/// how many operations the server remembers for undo
+pub const HISTORY_LIMIT: usize = 500;
+
+/// one change to one token, stored as the token before and after so it can be played both ways.
+/// None means the token didn't exist
+#[derive(Clone, Debug)]
+pub struct Operation {
+    pub user: String,
+    /// the scene the token is on, tokens of other scenes can share its name
+    pub scene: String,
+    pub name: String,
+    pub before: Option<(Layer, Token)>,
+    pub after: Option<(Layer, Token)>,
+}
+
+/// server side undo/redo. every user walks back through their own operations only,
+/// so undoing never touches tokens someone else changed in the meantime. only operations
+/// on the scene passed in are played back
+#[derive(Default)]
+pub struct History {
+    pub done: Vec<Operation>,
+    pub undone: Vec<Operation>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+    pub fn record(
+        &mut self,
+        user: &String,
+        scene: &str,
+        name: String,
+        before: Option<(Layer, Token)>,
+        after: Option<(Layer, Token)>,
+    ) {
+        if before == after {
+            return;
+        }
+        self.undone.retain(|op| op.user != *user);
+        self.done.push(Operation {
+            user: user.clone(),
+            scene: scene.to_string(),
+            name,
+            before,
+            after,
+        });
+        if self.done.len() > HISTORY_LIMIT {
+            self.done.remove(0);
+        }
+    }
+    /// reverts the user's latest operation. operations on tokens someone else changed since are
+    /// dropped and the one before is tried. with everyone set (gm only) the latest operation of
+    /// anyone is reverted, even if the token changed since. returns whether anything changed
+    pub fn undo(&mut self, state: &mut State, user: &String, everyone: bool) -> bool {
+        while let Some(i) = self
+            .done
+            .iter()
+            .rposition(|op| op.scene == state.scene && (everyone || op.user == *user))
+        {
+            let op = self.done.remove(i);
+            if !everyone && state.snapshot(&op.name) != op.after {
+                // someone else changed the token after this user did, leave their change alone
+                continue;
+            }
+            state.restore(&op.name, op.before.clone());
+            self.undone.push(op);
+            return true;
+        }
+        false
+    }
+    /// plays the user's latest undone operation again, skipping stale ones like undo does
+    pub fn redo(&mut self, state: &mut State, user: &String, everyone: bool) -> bool {
+        while let Some(i) = self
+            .undone
+            .iter()
+            .rposition(|op| op.scene == state.scene && (everyone || op.user == *user))
+        {
+            let op = self.undone.remove(i);
+            if !everyone && state.snapshot(&op.name) != op.before {
+                continue;
+            }
+            state.restore(&op.name, op.after.clone());
+            self.done.push(op);
+            return true;
+        }
+        false
+    }
+}
+
+#[test]
+pub fn undo_is_per_user_test() {
+    let mut state = State::new();
+    let mut history = History::new();
+    let (a, b) = (String::from("a"), String::from("b"));
+    for (user, name) in [(&a, "ta"), (&b, "tb")] {
+        let before = state.snapshot(&name.into());
+        state.create_token(name.into(), Token::default(), &Layer::Base);
+        history.record(
+            user,
+            &state.scene,
+            name.into(),
+            before,
+            state.snapshot(&name.into()),
+        );
+    }
+    assert!(history.undo(&mut state, &a, false));
+    assert!(!state.contains_token(&"ta".into()));
+    assert!(state.contains_token(&"tb".into()));
+    assert!(!history.undo(&mut state, &a, false));
+    assert!(history.redo(&mut state, &a, false));
+    assert!(state.contains_token(&"ta".into()));
+    assert!(history.undo(&mut state, &a, true));
+    assert!(!state.contains_token(&"ta".into()));
+    // a stale operation is skipped and the one before it undone
+    for name in ["first", "second"] {
+        let before = state.snapshot(&name.into());
+        state.create_token(name.into(), Token::default(), &Layer::Base);
+        history.record(
+            &a,
+            &state.scene,
+            name.into(),
+            before,
+            state.snapshot(&name.into()),
+        );
+    }
+    state.destroy_token(&"second".into());
+    assert!(history.undo(&mut state, &a, false));
+    assert!(!state.contains_token(&"first".into()));
+    // operations of another scene are left alone, even by the gm undoing anyone
+    state.scene = "other".into();
+    assert!(!history.undo(&mut state, &a, true));
+}
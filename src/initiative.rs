@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+/// one token's place in the turn order
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Combatant {
+    pub token: String,
+    pub initiative: i32,
+}
+
+/// combat turn order, kept in the state so every client shows the same tracker
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Initiative {
+    pub order: Vec<Combatant>,
+    /// index into order of the token whose turn it is
+    pub turn: usize,
+    /// starts at 1 once someone is in the order
+    pub round: u32,
+}
+
+impl Initiative {
+    pub fn contains(&self, token: &String) -> bool {
+        self.order.iter().any(|c| c.token == *token)
+    }
+    pub fn current(&self) -> Option<&Combatant> {
+        self.order.get(self.turn)
+    }
+    pub fn add(&mut self, token: String, initiative: i32) {
+        if self.contains(&token) {
+            return;
+        }
+        self.order.push(Combatant { token, initiative });
+        self.round = self.round.max(1);
+    }
+    /// takes a token out, the turn stays with whoever had it
+    pub fn remove(&mut self, token: &String) {
+        let Some(i) = self.order.iter().position(|c| c.token == *token) else {
+            return;
+        };
+        self.order.remove(i);
+        if i < self.turn {
+            self.turn -= 1;
+        }
+        if self.turn >= self.order.len() {
+            self.turn = 0;
+        }
+        if self.order.is_empty() {
+            self.round = 0;
+        }
+    }
+    pub fn set(&mut self, token: &String, initiative: i32) {
+        if let Some(c) = self.order.iter_mut().find(|c| c.token == *token) {
+            c.initiative = initiative;
+        }
+    }
+    /// highest initiative first, ties keep their order. the turn follows the token that had it
+    pub fn sort(&mut self) {
+        let current = self.current().map(|c| c.token.clone());
+        self.order.sort_by_key(|c| std::cmp::Reverse(c.initiative));
+        if let Some(current) = current {
+            self.turn = self
+                .order
+                .iter()
+                .position(|c| c.token == current)
+                .unwrap_or(0);
+        }
+    }
+    /// hands the turn to the next token, going around starts a new round
+    pub fn next_turn(&mut self) {
+        if self.order.is_empty() {
+            return;
+        }
+        self.turn += 1;
+        if self.turn >= self.order.len() {
+            self.turn = 0;
+            self.round += 1;
+        }
+    }
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[test]
+pub fn initiative_turns_test() {
+    let mut init = Initiative::default();
+    for (t, v) in [("a", 5), ("b", 18), ("c", 12)] {
+        init.add(t.into(), v);
+    }
+    init.sort();
+    let names: Vec<&str> = init.order.iter().map(|c| c.token.as_str()).collect();
+    assert_eq!(names, ["b", "c", "a"]);
+    // a was first before sorting and keeps the turn
+    assert_eq!(init.current().unwrap().token, "a");
+    init.next_turn();
+    assert_eq!(init.current().unwrap().token, "b");
+    assert_eq!(init.round, 2);
+    init.next_turn();
+    init.remove(&"b".into());
+    assert_eq!(init.current().unwrap().token, "c");
+}
@@ -0,0 +1,118 @@
+use eframe::egui::Pos2;
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+
+/// who gets to read a journal entry
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum Visibility {
+    /// only the gm
+    #[default]
+    Private,
+    Everyone,
+    Players(Vec<String>),
+}
+
+/// a handout or note written by the gm
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct JournalEntry {
+    pub title: String,
+    pub text: String,
+    /// images in the session folder
+    pub images: Vec<String>,
+    pub visibility: Visibility,
+}
+
+impl JournalEntry {
+    pub fn visible_to(&self, user: &String) -> bool {
+        match &self.visibility {
+            Visibility::Private => false,
+            Visibility::Everyone => true,
+            Visibility::Players(players) => players.contains(user),
+        }
+    }
+}
+
+/// a marker on the board that opens a journal entry
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Pin {
+    pub location: Pos2,
+    pub entry: String,
+}
+
+impl State {
+    pub fn allocate_entry_id(&mut self) -> String {
+        self.next_entry_id += 1;
+        format!("j{}", self.next_entry_id)
+    }
+    pub fn allocate_pin_id(&mut self) -> String {
+        self.next_entry_id += 1;
+        format!("p{}", self.next_entry_id)
+    }
+    /// images the journal shows, so they can be sent to clients and bundled
+    pub fn journal_images(&self) -> impl Iterator<Item = &String> {
+        self.journal.values().flat_map(|e| e.images.iter())
+    }
+    /// removes an entry along with the pins leading to it in every scene
+    pub fn remove_entry(&mut self, id: &String) {
+        self.journal.remove(id);
+        self.pins.retain(|_, p| p.entry != *id);
+        for s in self.scenes.stored.values_mut() {
+            s.pins.retain(|_, p| p.entry != *id);
+        }
+    }
+    /// players get the entries shown to them, the pins to those and only their own notes
+    pub fn hide_journal(&mut self, user: &String, gm: bool) {
+        self.notes.retain(|u, _| u == user);
+        if gm {
+            return;
+        }
+        self.journal.retain(|_, e| e.visible_to(user));
+        let journal = &self.journal;
+        self.pins.retain(|_, p| journal.contains_key(&p.entry));
+    }
+}
+
+#[test]
+pub fn journal_visibility_test() {
+    let mut state = State::new();
+    let (alice, bob) = (String::from("alice"), String::from("bob"));
+    for (id, visibility) in [
+        ("secret", Visibility::Private),
+        ("map", Visibility::Everyone),
+        ("letter", Visibility::Players(vec![alice.clone()])),
+    ] {
+        state.journal.insert(
+            id.into(),
+            JournalEntry {
+                visibility,
+                images: vec![format!("{}.png", id)],
+                ..Default::default()
+            },
+        );
+        state.pins.insert(
+            format!("{}_pin", id),
+            Pin {
+                location: Pos2::new(150.0, 150.0),
+                entry: id.into(),
+            },
+        );
+    }
+    state
+        .notes
+        .insert(alice.clone(), "bob is suspicious".into());
+    state
+        .notes
+        .insert(bob.clone(), "alice is suspicious".into());
+    let seen = state.for_client(&bob, false);
+    assert_eq!(seen.journal.len(), 1);
+    assert_eq!(seen.pins.len(), 1);
+    assert_eq!(seen.notes.len(), 1);
+    assert_eq!(seen.notes[&bob], "alice is suspicious");
+    assert_eq!(seen.shown_images().collect::<Vec<_>>(), ["map.png"]);
+    assert_eq!(state.for_client(&alice, false).journal.len(), 2);
+    assert_eq!(state.for_client(&"gm".into(), true).journal.len(), 3);
+    state.remove_entry(&"map".into());
+    assert!(!state.pins.contains_key("map_pin"));
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use eframe::egui::{Color32, Pos2, epaint::Mesh};
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+use crate::fog::{self, CELL, Cell};
+use crate::vision;
+
+/// how much a cell in dim light is lit, bright light is 1
+pub const DIM_LEVEL: f32 = 0.5;
+
+/// a light carried by a token, radii are in cells and 0 means no light
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Light {
+    pub bright: f32,
+    /// dim light reaches from the edge of the bright light out to here
+    pub dim: f32,
+    pub color: Color32,
+}
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            bright: 0.0,
+            dim: 0.0,
+            color: Color32::from_rgb(255, 220, 150),
+        }
+    }
+}
+impl Light {
+    pub fn is_lit(&self) -> bool {
+        self.bright > 0.0 || self.dim > 0.0
+    }
+    /// how far the light reaches in pixels
+    pub fn reach(&self) -> f32 {
+        self.bright.max(self.dim) * CELL
+    }
+}
+
+/// a light on the board, with the area the walls leave it
+pub struct LitArea {
+    pub center: Pos2,
+    pub light: Light,
+    pub polygon: Vec<Pos2>,
+}
+
+impl State {
+    pub fn lit_areas(&self) -> Vec<LitArea> {
+        let walls = self.sight_blockers();
+        self.all_tokens()
+            .filter(|t| t.light.is_lit())
+            .map(|t| {
+                let center = t.location + t.size() * 0.5;
+                LitArea {
+                    center,
+                    light: t.light,
+                    polygon: vision::visibility_polygon(center, t.light.reach(), &walls),
+                }
+            })
+            .collect()
+    }
+    /// light level of every lit cell, from 0 for dark to 1 for bright light. cells not in the map are dark
+    pub fn light_levels(&self) -> HashMap<Cell, f32> {
+        let areas = self.lit_areas();
+        let mut levels = HashMap::new();
+        if areas.is_empty() {
+            return levels;
+        }
+        for c in fog::all_cells() {
+            let p = fog::cell_rect(c).center();
+            let level = areas
+                .iter()
+                .filter(|a| fog::point_in_polygon(p, &a.polygon))
+                .map(|a| {
+                    if p.distance(a.center) <= a.light.bright * CELL {
+                        1.0
+                    } else {
+                        DIM_LEVEL
+                    }
+                })
+                .fold(0.0, f32::max);
+            if level > 0.0 {
+                levels.insert(c, level);
+            }
+        }
+        levels
+    }
+}
+
+/// a fan from the light's center out to its polygon, fading from its color to nothing
+pub fn glow_mesh(area: &LitArea) -> Mesh {
+    let mut mesh = Mesh::default();
+    if area.polygon.len() < 3 {
+        return mesh;
+    }
+    let [r, g, b, _] = area.light.color.to_array();
+    mesh.colored_vertex(area.center, Color32::from_rgba_unmultiplied(r, g, b, 70));
+    for p in &area.polygon {
+        mesh.colored_vertex(*p, Color32::TRANSPARENT);
+    }
+    let n = area.polygon.len() as u32;
+    for i in 0..n {
+        mesh.add_triangle(0, 1 + i, 1 + (i + 1) % n);
+    }
+    mesh
+}
+
+/// one dark square per cell, lighter where the cell is lit. max_alpha is what a fully dark cell gets
+pub fn darkness_mesh(darkness: f32, levels: &HashMap<Cell, f32>, max_alpha: f32) -> Mesh {
+    let mut mesh = Mesh::default();
+    for c in fog::all_cells() {
+        let level = levels.get(&c).copied().unwrap_or(0.0);
+        let alpha = (darkness * (1.0 - level) * max_alpha).round() as u8;
+        if alpha > 0 {
+            mesh.add_colored_rect(fog::cell_rect(c), Color32::from_black_alpha(alpha));
+        }
+    }
+    mesh
+}
+
+#[test]
+pub fn light_levels_test() {
+    let mut state = State::new();
+    state.tokens.insert(
+        "torch".into(),
+        Token {
+            location: Pos2::new(200.0, 200.0),
+            light: Light {
+                bright: 2.0,
+                dim: 4.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    let levels = state.light_levels();
+    let at = |p: Pos2| levels.get(&fog::cell_of(p)).copied().unwrap_or(0.0);
+    assert_eq!(at(Pos2::new(230.0, 210.0)), 1.0);
+    assert_eq!(at(Pos2::new(270.0, 210.0)), DIM_LEVEL);
+    assert_eq!(at(Pos2::new(400.0, 400.0)), 0.0);
+    state.walls.insert(
+        "w".into(),
+        vision::Wall {
+            a: Pos2::new(240.0, 100.0),
+            b: Pos2::new(240.0, 300.0),
+            kind: vision::WallKind::Wall,
+            open: false,
+        },
+    );
+    let levels = state.light_levels();
+    assert!(!levels.contains_key(&fog::cell_of(Pos2::new(270.0, 210.0))));
+}
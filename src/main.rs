@@ -6,15 +6,41 @@ use eframe::{
 };
 
 use crate::client::Client;
+pub mod aoe;
+pub mod bundle;
+pub mod cards;
+pub mod chess;
 pub mod client;
 pub mod communication;
 pub mod database;
+pub mod dice;
+pub mod fog;
+pub mod history;
+pub mod initiative;
+pub mod journal;
+pub mod lighting;
+pub mod persistence;
+pub mod pointers;
+pub mod rules;
+pub mod scenes;
 pub mod server;
+pub mod sheets;
 pub mod utils;
+pub mod vision;
 pub struct GuiState {
     pub client: Client,
 }
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--data-dir" {
+            if let Some(dir) = args.next() {
+                communication::set_data_root(&dir);
+            }
+        } else if let Some(dir) = arg.strip_prefix("--data-dir=") {
+            communication::set_data_root(dir);
+        }
+    }
     gui_run()?;
     Ok(())
 }
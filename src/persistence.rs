@@ -0,0 +1,276 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::communication::*;
+use crate::throws;
+
+/// version written into the header of every new .bored file
+pub const SAVE_VERSION: u32 = 2;
+pub const SAVE_FORMAT: &str = "boredgames";
+pub const SAVE_EXTENSION: &str = ".bored";
+
+/// upgrades the state json of version i to version i + 1, indexed by i
+const MIGRATIONS: [fn(&mut Value); SAVE_VERSION as usize] = [migrate_v0, migrate_v1];
+
+/// version 0 was the bare State json without a header, its fields are unchanged
+fn migrate_v0(_state: &mut Value) {}
+
+/// version 1 tokens had a single square scale instead of a width and height
+fn migrate_v1(state: &mut Value) {
+    for layer in ["tokens", "map", "gm"] {
+        let Some(Value::Object(tokens)) = state.get_mut(layer) else {
+            continue;
+        };
+        for t in tokens.values_mut() {
+            if let Value::Object(t) = t
+                && let Some(scale) = t.remove("scale")
+            {
+                t.insert("width".into(), scale.clone());
+                t.insert("height".into(), scale);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub format: String,
+    pub version: u32,
+    pub state: Value,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Corrupt(String),
+    TooNew { version: u32 },
+}
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "could not read save: {}", e),
+            SaveError::Corrupt(e) => write!(f, "save is corrupt: {}", e),
+            SaveError::TooNew { version } => write!(
+                f,
+                "save is version {} but this build only reads up to version {}",
+                version, SAVE_VERSION
+            ),
+        }
+    }
+}
+impl std::error::Error for SaveError {}
+
+pub fn encode_save(state: &State) -> throws!(String) {
+    let file = SaveFile {
+        format: SAVE_FORMAT.into(),
+        version: SAVE_VERSION,
+        state: serde_json::to_value(state)?,
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+/// reads a .bored file of any known version, running the migrations up to the current one
+pub fn decode_save(s: &str) -> Result<State, SaveError> {
+    let value: Value = serde_json::from_str(s).map_err(|e| SaveError::Corrupt(e.to_string()))?;
+    let (version, mut state) = if value.get("format").is_some() {
+        let file: SaveFile =
+            serde_json::from_value(value).map_err(|e| SaveError::Corrupt(e.to_string()))?;
+        if file.format != SAVE_FORMAT {
+            return Err(SaveError::Corrupt(format!(
+                "unknown format {:#?}",
+                file.format
+            )));
+        }
+        (file.version, file.state)
+    } else {
+        (0, value)
+    };
+    if version > SAVE_VERSION {
+        return Err(SaveError::TooNew { version });
+    }
+    for m in &MIGRATIONS[version as usize..] {
+        m(&mut state);
+    }
+    serde_json::from_value(state).map_err(|e| SaveError::Corrupt(e.to_string()))
+}
+
+/// how many old copies of a save are kept next to it as name.1, name.2, ...
+pub const BACKUP_COUNT: usize = 3;
+/// minimum time between two saves triggered by state changes
+pub const CHANGE_DEBOUNCE: Duration = Duration::from_secs(2);
+/// how often the save is rotated into the backups while the state keeps changing
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+pub fn autosave_dir(session_dir: &str) -> String {
+    let d = session_dir.to_string() + "autosave/";
+    let _ = std::fs::create_dir_all(&d);
+    d
+}
+
+pub fn autosave_file(session_dir: &str) -> String {
+    autosave_dir(session_dir) + "session.bored"
+}
+
+/// whether a save name from a client stays inside the saves folder
+pub fn valid_save_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains("..")
+        && !name.contains(['/', '\\', ':'])
+        && !std::path::Path::new(name).is_absolute()
+}
+
+/// writes to a temporary file next to the target and renames it over the target,
+/// so a crash mid-write never leaves a half written save behind
+pub fn write_atomic(target: &str, bytes: &[u8]) -> throws!() {
+    let tmp = target.to_string() + ".tmp";
+    let mut file = std::fs::File::create(&tmp)?;
+    std::io::Write::write_all(&mut file, bytes)?;
+    // the data has to be on disk before the rename makes it the save
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, target)?;
+    Ok(())
+}
+
+/// shifts target.1 -> target.2 ... and copies target to target.1
+pub fn rotate_backups(target: &str, count: usize) -> throws!() {
+    if count == 0 || !std::fs::exists(target)? {
+        return Ok(());
+    }
+    for i in (1..count).rev() {
+        let from = format!("{}.{}", target, i);
+        if std::fs::exists(&from)? {
+            std::fs::rename(&from, format!("{}.{}", target, i + 1))?;
+        }
+    }
+    std::fs::copy(target, format!("{}.1", target))?;
+    Ok(())
+}
+
+/// writes the state over target, first rotating the old file into the backups when asked
+pub fn save_state(state: &State, target: &str, backup: bool) -> throws!() {
+    let s = encode_save(state)?;
+    if backup {
+        rotate_backups(target, BACKUP_COUNT)?;
+    }
+    write_atomic(target, s.as_bytes())
+}
+
+pub fn load_state(target: &str) -> Result<State, SaveError> {
+    let s = std::fs::read_to_string(target).map_err(SaveError::Io)?;
+    decode_save(&s)
+}
+
+/// loads the last autosave, falling back to the newest readable backup
+pub fn restore_session(session_dir: &str) -> Option<State> {
+    let target = autosave_file(session_dir);
+    if let Ok(s) = load_state(&target) {
+        return Some(s);
+    }
+    for i in 1..=BACKUP_COUNT {
+        if let Ok(s) = load_state(&format!("{}.{}", target, i)) {
+            return Some(s);
+        }
+    }
+    None
+}
+
+pub struct AutoSaver {
+    pub target: String,
+    pub dirty: bool,
+    pub changed_since_checkpoint: bool,
+    pub last_save: Instant,
+    pub last_checkpoint: Instant,
+}
+
+impl AutoSaver {
+    pub fn new(target: String) -> Self {
+        Self {
+            target,
+            dirty: false,
+            changed_since_checkpoint: false,
+            last_save: Instant::now(),
+            last_checkpoint: Instant::now(),
+        }
+    }
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.changed_since_checkpoint = true;
+    }
+    /// saves once the debounce has passed after a change, and rotates a backup every interval
+    pub fn tick(&mut self, should_log: bool, state: &State) {
+        if self.changed_since_checkpoint && self.last_checkpoint.elapsed() >= AUTOSAVE_INTERVAL {
+            self.save(should_log, state, true);
+        } else if self.dirty && self.last_save.elapsed() >= CHANGE_DEBOUNCE {
+            self.save(should_log, state, false);
+        }
+    }
+    /// writes any pending change right away, used when the server shuts down
+    pub fn flush(&mut self, should_log: bool, state: &State) {
+        if self.dirty {
+            self.save(should_log, state, false);
+        }
+    }
+    fn save(&mut self, should_log: bool, state: &State, checkpoint: bool) {
+        self.last_save = Instant::now();
+        if let Err(e) = save_state(state, &self.target, checkpoint) {
+            println!("autosave failed:{:#?}", e);
+            return;
+        }
+        self.dirty = false;
+        if checkpoint {
+            self.last_checkpoint = Instant::now();
+            self.changed_since_checkpoint = false;
+        }
+        if should_log {
+            println!("autosaved to {}", self.target);
+        }
+    }
+}
+#[test]
+pub fn save_name_test() {
+    assert!(valid_save_name("dungeon level 2"));
+    for bad in ["", "../x", "a/b", "..", "c:\\x", "/etc/passwd"] {
+        assert!(!valid_save_name(bad), "{}", bad);
+    }
+}
+
+#[test]
+pub fn rotate_backups_test() -> throws!() {
+    let dir = std::env::temp_dir().join("boredgames_rotate_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let target = dir.join("save.bored").to_string_lossy().to_string();
+    for i in 0..5 {
+        rotate_backups(&target, BACKUP_COUNT)?;
+        write_atomic(&target, i.to_string().as_bytes())?;
+    }
+    assert_eq!(std::fs::read_to_string(&target)?, "4");
+    assert_eq!(std::fs::read_to_string(format!("{}.1", target))?, "3");
+    assert_eq!(std::fs::read_to_string(format!("{}.3", target))?, "1");
+    assert!(!std::fs::exists(format!("{}.4", target))?);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+#[test]
+pub fn save_migration_test() -> throws!() {
+    let legacy = r#"{"messages":[["a","hi"]],"tokens":{"t":{"location":{"x":120.0,"y":140.0},"scale":2,"image":"orc.png"}},"map":{},"gm":{},"name":"old"}"#;
+    let state = decode_save(legacy)?;
+    assert_eq!(state.name, "old");
+    assert_eq!((state.tokens["t"].width, state.tokens["t"].height), (2, 2));
+    let state = decode_save(&encode_save(&state)?)?;
+    assert_eq!(state.tokens["t"].image, "orc.png");
+    let newer = format!(
+        r#"{{"format":"{}","version":{},"state":{{}}}}"#,
+        SAVE_FORMAT,
+        SAVE_VERSION + 1
+    );
+    assert!(matches!(decode_save(&newer), Err(SaveError::TooNew { .. })));
+    assert!(matches!(
+        decode_save("{not json"),
+        Err(SaveError::Corrupt(_))
+    ));
+    Ok(())
+}
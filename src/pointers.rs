@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// least time between two pings from one user, the server drops pings that come faster
+pub const PING_INTERVAL: Duration = Duration::from_millis(250);
+/// least time between two cursor updates from one user
+pub const CURSOR_INTERVAL: Duration = Duration::from_millis(50);
+/// how long a ping's ripple lasts
+pub const PING_LIFETIME: Duration = Duration::from_millis(1500);
+/// cursors that haven't moved for this long are hidden
+pub const CURSOR_LIFETIME: Duration = Duration::from_secs(5);
+
+/// lets through one event per user every interval and drops the rest. pings and
+/// cursors are never saved, so losing one costs nothing. the server keys it on the user
+/// a connection belongs to, so a client can't dodge it by claiming other names
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    every: Duration,
+    last: HashMap<String, Instant>,
+}
+
+impl RateLimit {
+    pub fn new(every: Duration) -> Self {
+        RateLimit {
+            every,
+            last: HashMap::new(),
+        }
+    }
+    pub fn allow(&mut self, user: &String, now: Instant) -> bool {
+        match self.last.get(user) {
+            Some(t) if now.saturating_duration_since(*t) < self.every => false,
+            _ => {
+                self.last.insert(user.clone(), now);
+                true
+            }
+        }
+    }
+}
+
+#[test]
+pub fn rate_limit_test() {
+    let mut limit = RateLimit::new(PING_INTERVAL);
+    let (a, b) = (String::from("a"), String::from("b"));
+    let start = Instant::now();
+    assert!(limit.allow(&a, start));
+    assert!(!limit.allow(&a, start + Duration::from_millis(100)));
+    assert!(limit.allow(&b, start + Duration::from_millis(100)));
+    assert!(limit.allow(&a, start + PING_INTERVAL));
+    assert!(!limit.allow(&a, start + PING_INTERVAL + Duration::from_millis(10)));
+}
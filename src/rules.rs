@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chess;
+use crate::communication::*;
+
+/// name of the rule set that allows everything
+pub const FREE_FORM: &str = "free form";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Outcome {
+    Win { player: String },
+    Draw,
+}
+
+/// the game being played on the board, kept in the state so clients can show whose turn it is
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Game {
+    /// which rule set the server runs, empty is free form
+    pub rules: String,
+    /// players in turn order, empty when the rules don't take turns
+    pub players: Vec<String>,
+    pub turn: usize,
+    /// set once the game is over, moves are refused after that
+    pub outcome: Option<Outcome>,
+    /// the position the game started from and the one it is in now, in the rules' own notation
+    pub setup: String,
+    pub position: String,
+    /// every move so far in the rules' own notation
+    pub record: Vec<String>,
+}
+
+impl Game {
+    pub fn current_player(&self) -> Option<&String> {
+        self.players.get(self.turn)
+    }
+    pub fn advance(&mut self) {
+        if !self.players.is_empty() {
+            self.turn = (self.turn + 1) % self.players.len();
+        }
+    }
+    /// refuses anyone but the player whose turn it is, for rules that take turns
+    pub fn check_turn(&self, user: &String) -> Result<(), String> {
+        match self.current_player() {
+            Some(p) if p != user => Err(format!("it is {}'s turn", p)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// a rule set the server checks board edits against. everything has a default
+/// so a rule set only needs what it cares about
+pub trait GameRules: Send {
+    fn name(&self) -> &'static str;
+    /// lays out the board when the game starts. from is an optional saved game to carry on from
+    fn setup(&mut self, _state: &mut State, _from: &str) -> Result<(), String> {
+        Ok(())
+    }
+    /// choice is what the mover picked for moves that need one, like the piece a pawn promotes to
+    fn check_move(
+        &self,
+        _state: &State,
+        _user: &String,
+        _name: &String,
+        _to: &Token,
+        _choice: &str,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+    fn check_create(
+        &self,
+        _state: &State,
+        _user: &String,
+        _token: &Token,
+        _layer: &Layer,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+    /// any change to a token other than a move: inspector edits, reordering, changing
+    /// its layer or destroying it
+    fn check_change(&self, _state: &State, _user: &String, _name: &String) -> Result<(), String> {
+        Ok(())
+    }
+    /// applies a move that passed check_move, rules that capture or promote pieces do it here
+    fn apply_move(
+        &mut self,
+        state: &mut State,
+        _user: &String,
+        name: &String,
+        to: &Token,
+        _choice: &str,
+    ) {
+        state.update_token(name, to);
+    }
+    /// called after every applied move to hand the turn on and decide whether the game is over
+    fn after_move(&mut self, _state: &mut State, _user: &String) {}
+    /// rules that keep their own position can't have single tokens rolled back
+    fn allows_undo(&self) -> bool {
+        true
+    }
+}
+
+/// today's behaviour, anyone moves anything at any time
+pub struct FreeForm;
+impl GameRules for FreeForm {
+    fn name(&self) -> &'static str {
+        FREE_FORM
+    }
+}
+
+/// every rule set a host can pick
+pub fn available_rules() -> Vec<&'static str> {
+    vec![FREE_FORM, chess::CHESS]
+}
+
+/// the rule set with the given name, free form when there is none
+pub fn rules_by_name(name: &str) -> Box<dyn GameRules> {
+    match name {
+        chess::CHESS => Box::new(chess::Chess),
+        _ => Box::new(FreeForm),
+    }
+}
+
+/// checks a move against the rules and the game state, and applies it if it is allowed
+pub fn try_move(
+    rules: &mut dyn GameRules,
+    state: &mut State,
+    user: &String,
+    name: &String,
+    to: &Token,
+    choice: &str,
+) -> Result<(), String> {
+    if state.game.outcome.is_some() {
+        return Err("the game is over".into());
+    }
+    rules.check_move(state, user, name, to, choice)?;
+    rules.apply_move(state, user, name, to, choice);
+    rules.after_move(state, user);
+    Ok(())
+}
+
+#[test]
+pub fn game_rules_test() {
+    struct Alternating;
+    impl GameRules for Alternating {
+        fn name(&self) -> &'static str {
+            "alternating"
+        }
+        fn check_move(
+            &self,
+            state: &State,
+            user: &String,
+            _name: &String,
+            _to: &Token,
+            _choice: &str,
+        ) -> Result<(), String> {
+            state.game.check_turn(user)
+        }
+        fn after_move(&mut self, state: &mut State, _user: &String) {
+            state.game.advance();
+        }
+    }
+    let mut state = State::new();
+    state.tokens.insert("t".into(), Token::default());
+    let (a, b) = (String::from("a"), String::from("b"));
+    let to = Token {
+        location: eframe::egui::Pos2::new(120.0, 120.0),
+        ..Default::default()
+    };
+    assert!(try_move(&mut FreeForm, &mut state, &b, &"t".into(), &to, "").is_ok());
+    state.game.players = vec![a.clone(), b.clone()];
+    let mut rules = Alternating;
+    assert!(try_move(&mut rules, &mut state, &b, &"t".into(), &to, "").is_err());
+    assert!(try_move(&mut rules, &mut state, &a, &"t".into(), &to, "").is_ok());
+    assert!(try_move(&mut rules, &mut state, &b, &"t".into(), &to, "").is_ok());
+    assert!(FreeForm.check_change(&state, &a, &"t".into()).is_ok());
+    assert!(
+        rules_by_name(chess::CHESS)
+            .check_change(&state, &a, &"t".into())
+            .is_err()
+    );
+    state.game.outcome = Some(Outcome::Draw);
+    assert!(try_move(&mut FreeForm, &mut state, &a, &"t".into(), &to, "").is_err());
+}
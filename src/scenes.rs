@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem::take;
+
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+
+/// the scenes of a session besides the one in the state that holds them.
+/// on the server the state holding them is the scene players are on
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenes {
+    /// every other scene by name. the gm's client gets the names of all scenes
+    /// here, this one included, and players get none
+    pub stored: BTreeMap<String, State>,
+    /// users moved to a scene of their own, like the gm previewing one
+    pub assigned: HashMap<String, String>,
+    /// the scene everyone not assigned elsewhere is on
+    pub players: String,
+}
+
+impl State {
+    /// all scene names in the session, this one included
+    pub fn scene_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.scenes.stored.keys().cloned().collect();
+        names.push(self.scene.clone());
+        names.sort_unstable();
+        names
+    }
+    pub fn has_scene(&self, name: &String) -> bool {
+        *name == self.scene || self.scenes.stored.contains_key(name)
+    }
+    pub fn scene_of(&self, user: &String) -> String {
+        self.scenes
+            .assigned
+            .get(user)
+            .filter(|s| self.has_scene(s))
+            .unwrap_or(&self.scenes.players)
+            .clone()
+    }
+    /// the users other than user who are on the same scene as them
+    pub fn same_scene<'a>(
+        &self,
+        users: impl Iterator<Item = &'a String>,
+        user: &String,
+    ) -> Vec<&'a String> {
+        let scene = self.scene_of(user);
+        users
+            .filter(|u| *u != user && self.scene_of(u) == scene)
+            .collect()
+    }
+    /// makes another scene the current one. chat, token ids and the scene list stay
+    /// with the session, the rest of the old scene is put away
+    pub fn enter_scene(&mut self, name: &String) -> bool {
+        if *name == self.scene {
+            return true;
+        }
+        let Some(mut next) = self.scenes.stored.remove(name) else {
+            return false;
+        };
+        next.take_session(self);
+        next.next_token_id = self.next_token_id.max(next.next_token_id);
+        let scenes = take(&mut self.scenes);
+        let old = std::mem::replace(self, next);
+        self.scenes = scenes;
+        self.scenes.stored.insert(old.scene.clone(), old);
+        true
+    }
+    /// moves over what belongs to the session rather than a scene
+    fn take_session(&mut self, from: &mut State) {
+        self.messages = take(&mut from.messages);
+        self.journal = take(&mut from.journal);
+        self.next_entry_id = from.next_entry_id;
+        self.notes = take(&mut from.notes);
+        self.sheet_templates = take(&mut from.sheet_templates);
+    }
+    /// puts a loaded map in place of the current scene. the session, its other scenes
+    /// and who is on which of them stay as they are
+    pub fn replace_scene(&mut self, mut loaded: State) {
+        loaded.take_session(self);
+        loaded.scene = self.scene.clone();
+        loaded.scenes = take(&mut self.scenes);
+        loaded.next_token_id = self.next_token_id.max(loaded.next_token_id);
+        *self = loaded;
+    }
+    /// tokens of every scene in the session
+    pub fn session_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.all_tokens()
+            .chain(self.scenes.stored.values().flat_map(|s| s.all_tokens()))
+    }
+    pub fn session_tokens_mut(&mut self) -> impl Iterator<Item = &mut Token> {
+        self.tokens
+            .values_mut()
+            .chain(self.map.values_mut())
+            .chain(self.gm.values_mut())
+            .chain(
+                self.scenes
+                    .stored
+                    .values_mut()
+                    .flat_map(|s| s.all_tokens_mut()),
+            )
+    }
+    /// back to the scene players are on
+    pub fn go_home(&mut self) {
+        let home = self.scenes.players.clone();
+        if !self.enter_scene(&home) {
+            self.scenes.players = self.scene.clone();
+        }
+    }
+    /// stores a new scene under a name no other scene has
+    pub fn add_scene(&mut self, mut scene: State) -> String {
+        let base = if scene.scene.is_empty() {
+            "scene".to_string()
+        } else {
+            scene.scene.clone()
+        };
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.has_scene(&name) {
+            n += 1;
+            name = format!("{} {}", base, n);
+        }
+        scene.scene = name.clone();
+        scene.messages.clear();
+        scene.scenes = Scenes::default();
+        self.scenes.stored.insert(name.clone(), scene);
+        name
+    }
+    /// the scene a user is on as that user may see it
+    pub fn view_for(&self, user: &String, gm: bool) -> State {
+        let scene = self.scene_of(user);
+        let mut out = match self.scenes.stored.get(&scene) {
+            Some(s) if scene != self.scene => {
+                let mut s = s.clone();
+                s.messages = self.messages.clone();
+                s.journal = self.journal.clone();
+                s.next_entry_id = self.next_entry_id;
+                s.notes = self.notes.clone();
+                s.sheet_templates = self.sheet_templates.clone();
+                s
+            }
+            _ => {
+                let mut s = self.clone();
+                s.scenes = Scenes::default();
+                s
+            }
+        }
+        .for_client(user, gm);
+        // only the gm learns which scenes there are
+        out.scenes = if gm {
+            Scenes {
+                stored: self
+                    .scene_names()
+                    .into_iter()
+                    .map(|n| (n, State::default()))
+                    .collect(),
+                assigned: self.scenes.assigned.clone(),
+                players: self.scenes.players.clone(),
+            }
+        } else {
+            Scenes {
+                players: self.scenes.players.clone(),
+                ..Default::default()
+            }
+        };
+        out
+    }
+}
+
+#[test]
+pub fn scene_switch_test() {
+    let mut state = State::new();
+    state.scene = "town".into();
+    state.scenes.players = "town".into();
+    state.messages.push(("gm".into(), "hi".into()));
+    state.tokens.insert("t".into(), Token::default());
+    let cave = state.add_scene(State {
+        scene: "cave".into(),
+        ..State::new()
+    });
+    let (gm, player) = (String::from("gm"), String::from("player"));
+    state.scenes.assigned.insert(gm.clone(), cave.clone());
+    assert!(state.view_for(&gm, true).tokens.is_empty());
+    assert_eq!(state.view_for(&player, false).tokens.len(), 1);
+    assert!(state.enter_scene(&cave));
+    state.messages.push(("gm".into(), "in the cave".into()));
+    state.go_home();
+    assert_eq!(state.scene, "town");
+    assert_eq!(state.messages.len(), 2);
+    assert!(state.view_for(&player, false).scenes.stored.is_empty());
+    assert_eq!(state.view_for(&gm, true).scenes.stored.len(), 2);
+    state
+        .notes
+        .insert(player.clone(), "remember the cave".into());
+    state.replace_scene(State {
+        scene: "loaded".into(),
+        ..State::new()
+    });
+    assert_eq!(state.scene, "town");
+    assert!(state.tokens.is_empty());
+    assert_eq!(state.messages.len(), 2);
+    assert_eq!(state.notes.len(), 1);
+    assert_eq!(state.scene_of(&gm), cave);
+    assert!(state.has_scene(&cave));
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
     io::ErrorKind,
     net::{TcpListener, TcpStream},
@@ -7,8 +7,19 @@ use std::{
     thread::JoinHandle,
 };
 
+use crate::cards::CardPlace;
 use crate::communication::*;
-use crate::utils::{read_object, try_read_object, write_object};
+use crate::fog::Fog;
+use crate::history::History;
+use crate::initiative::Initiative;
+use crate::journal::Pin;
+use crate::persistence::{self, AutoSaver};
+use crate::pointers::{self, RateLimit};
+use crate::rules::{self, Game};
+use crate::scenes::Scenes;
+use crate::sheets::{self, Sheet};
+use crate::utils::{Rng, read_object, try_read_object, write_object};
+use crate::vision::WallKind;
 pub struct UserConnection {
     pub username: String,
     pub stream: TcpStream,
@@ -17,6 +28,8 @@ pub struct Server {
     pub clients: HashMap<String, UserConnection>,
     pub new_connections: Arc<Mutex<Vec<TcpStream>>>,
     pub owner: String,
+    /// session folder for uploaded images and the autosave
+    pub asset_dir: String,
 }
 impl Default for State {
     fn default() -> Self {
@@ -32,19 +45,231 @@ impl State {
             tokens: HashMap::new(),
             map: HashMap::new(),
             gm: HashMap::new(),
+            next_token_id: 0,
+            fog: Fog::default(),
+            walls: HashMap::new(),
+            next_wall_id: 0,
+            dynamic_vision: false,
+            darkness: 0.0,
+            initiative: Initiative::default(),
+            game: Game::default(),
+            decks: HashMap::new(),
+            next_deck_id: 0,
+            hands: HashMap::new(),
+            hidden_cards: HashMap::new(),
+            scene: String::new(),
+            scenes: Scenes::default(),
+            journal: BTreeMap::new(),
+            next_entry_id: 0,
+            notes: HashMap::new(),
+            pins: HashMap::new(),
+            sheet_templates: BTreeMap::new(),
+            sheets: HashMap::new(),
+            areas: HashMap::new(),
+            next_area_id: 0,
+        }
+    }
+    /// a token id that was never handed out before in this state
+    pub fn allocate_token_id(&mut self) -> String {
+        loop {
+            let id = format!("t{}", self.next_token_id);
+            self.next_token_id += 1;
+            if !self.contains_token(&id) {
+                return id;
+            }
+        }
+    }
+    pub fn layer_mut(&mut self, layer: &Layer) -> &mut HashMap<String, Token> {
+        match layer {
+            Layer::Base => &mut self.tokens,
+            Layer::Map => &mut self.map,
+            Layer::Gm => &mut self.gm,
+        }
+    }
+    pub fn all_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.tokens
+            .values()
+            .chain(self.map.values())
+            .chain(self.gm.values())
+    }
+    pub fn all_tokens_named(&self) -> impl Iterator<Item = &String> {
+        self.tokens
+            .keys()
+            .chain(self.map.keys())
+            .chain(self.gm.keys())
+    }
+    pub fn all_tokens_mut(&mut self) -> impl Iterator<Item = &mut Token> {
+        self.tokens
+            .values_mut()
+            .chain(self.map.values_mut())
+            .chain(self.gm.values_mut())
+    }
+    pub fn get_token(&self, name: &String) -> Option<&Token> {
+        self.tokens
+            .get(name)
+            .or_else(|| self.map.get(name))
+            .or_else(|| self.gm.get(name))
+    }
+    /// whether user may act for the token, the gm may act for any
+    pub fn controls(&self, name: &String, user: &String, gm: &String) -> bool {
+        user == gm || self.get_token(name).is_some_and(|t| t.owner == *user)
+    }
+    /// only the gm hands tokens to someone else, anyone else's edit keeps the current owner
+    pub fn keep_owner(&self, name: &String, token: &mut Token, user: &String, gm: &String) {
+        if user != gm
+            && let Some(t) = self.get_token(name)
+        {
+            token.owner = t.owner.clone();
+        }
+    }
+    /// dice, counters and cards only change through their own events, an edit or a move
+    /// keeps whatever the token has now
+    pub fn keep_object(&self, name: &String, token: &mut Token) {
+        if let Some(t) = self.get_token(name) {
+            token.object = t.object.clone();
+            token.card = t.card.clone();
+        }
+    }
+    /// updates leave a token where it is, moving goes through TokenMoved so the rules see it
+    /// and reordering through TokenReordered
+    pub fn keep_place(&self, name: &String, token: &mut Token) {
+        if let Some(t) = self.get_token(name) {
+            token.location = t.location;
+            token.z = t.z;
+        }
+    }
+    pub fn contains_token(&self, name: &String) -> bool {
+        self.tokens.contains_key(name) || self.map.contains_key(name) || self.gm.contains_key(name)
+    }
+    pub fn update_token(&mut self, name: &String, to: &Token) {
+        if let Some(t) = self.tokens.get_mut(name) {
+            *t = to.clone();
+        }
+        if let Some(t) = self.map.get_mut(name) {
+            *t = to.clone();
+        }
+        if let Some(t) = self.gm.get_mut(name) {
+            *t = to.clone();
+        }
+    }
+    pub fn layer_of(&self, name: &String) -> Option<Layer> {
+        if self.tokens.contains_key(name) {
+            Some(Layer::Base)
+        } else if self.map.contains_key(name) {
+            Some(Layer::Map)
+        } else if self.gm.contains_key(name) {
+            Some(Layer::Gm)
+        } else {
+            None
+        }
+    }
+    /// token names of a layer from bottom to top, ties broken by name so every client agrees
+    pub fn draw_order(tokens: &HashMap<String, Token>) -> Vec<String> {
+        let mut names: Vec<&String> = tokens.keys().collect();
+        names.sort_unstable_by_key(|n| (tokens[*n].z, *n));
+        names.into_iter().cloned().collect()
+    }
+    fn front_z(tokens: &HashMap<String, Token>) -> i64 {
+        tokens.values().map(|t| t.z + 1).max().unwrap_or(0)
+    }
+    fn back_z(tokens: &HashMap<String, Token>) -> i64 {
+        tokens.values().map(|t| t.z - 1).min().unwrap_or(0)
+    }
+    pub fn snapshot(&self, name: &String) -> Option<(Layer, Token)> {
+        let layer = self.layer_of(name)?;
+        Some((layer, self.get_token(name)?.clone()))
+    }
+    /// puts a token back exactly as a snapshot had it, or removes it for None
+    pub fn restore(&mut self, name: &String, snapshot: Option<(Layer, Token)>) {
+        let Some((layer, token)) = snapshot else {
+            self.destroy_token(name);
+            return;
+        };
+        // a token that stays keeps its place in the initiative order
+        self.map.remove(name);
+        self.tokens.remove(name);
+        self.gm.remove(name);
+        self.layer_mut(&layer).insert(name.clone(), token);
+    }
+    /// new tokens always go on top of their layer
+    pub fn create_token(&mut self, name: String, mut token: Token, layer: &Layer) {
+        if !self.contains_token(&name) {
+            let tokens = self.layer_mut(layer);
+            token.z = Self::front_z(tokens);
+            tokens.insert(name, token);
+        }
+    }
+    pub fn reorder_token(&mut self, name: &String, order: &ZOrder) {
+        let Some(layer) = self.layer_of(name) else {
+            return;
+        };
+        let tokens = self.layer_mut(&layer);
+        let z = match order {
+            ZOrder::Front => Self::front_z(tokens),
+            ZOrder::Back => Self::back_z(tokens),
+        };
+        if let Some(t) = tokens.get_mut(name) {
+            t.z = z;
+        }
+    }
+    pub fn change_token_layer(&mut self, name: &String, layer: &Layer) {
+        let Some(from) = self.layer_of(name) else {
+            return;
+        };
+        if from == *layer {
+            return;
+        }
+        if let Some(mut token) = self.layer_mut(&from).remove(name) {
+            let tokens = self.layer_mut(layer);
+            token.z = Self::front_z(tokens);
+            tokens.insert(name.clone(), token);
+        }
+    }
+    /// removes the token from whichever layer it is on, and from the initiative order
+    pub fn destroy_token(&mut self, name: &String) {
+        self.map.remove(name);
+        self.tokens.remove(name);
+        self.gm.remove(name);
+        self.initiative.remove(name);
+    }
+}
+/// asks the rules whether user may change the token and answers a refusal with
+/// ActionRejected. callers mark the state changed either way, so a refused change
+/// resends the state and the sender's copy snaps back to the server's
+fn change_allowed(
+    rules: &dyn rules::GameRules,
+    state: &State,
+    user: &String,
+    name: &String,
+    replies: &mut Vec<(String, EventData)>,
+) -> bool {
+    match rules.check_change(state, user, name) {
+        Ok(()) => true,
+        Err(reason) => {
+            replies.push((
+                user.clone(),
+                EventData::ActionRejected {
+                    name: name.clone(),
+                    reason,
+                },
+            ));
+            false
         }
     }
 }
 pub static SHOULD_DIE: AtomicBool = AtomicBool::new(false);
 pub static EXISTS: AtomicBool = AtomicBool::new(false);
 impl Server {
-    pub fn handle_client(should_log: bool, _name: &String, con: &mut UserConnection) -> Vec<Event> {
+    /// events waiting on a connection. their source is set to the user the connection
+    /// belongs to, so permission checks never rest on what a client claims to be
+    pub fn handle_client(should_log: bool, name: &str, con: &mut UserConnection) -> Vec<Event> {
         let mut events = Vec::new();
         let mut buf = Vec::new();
-        while let Some(t) = try_read_object::<Event>(&mut con.stream, &mut buf)
+        while let Some(mut t) = try_read_object::<Event>(&mut con.stream, &mut buf)
             .or_else(|_| Ok::<Option<Event>, Box<dyn Error>>(None))
             .unwrap()
         {
+            t.source = name.to_string();
             if should_log {
                 println!("log:{:#?}", t.source);
             }
@@ -54,21 +279,35 @@ impl Server {
     }
 
     pub fn handle_clients(should_log: bool, mut this: Self, handle: JoinHandle<()>) {
-        let mut app_state = State {
-            name: String::new(),
-            messages: Vec::new(),
-            tokens: HashMap::new(),
-            map: HashMap::new(),
-            gm: HashMap::new(),
-        };
+        let mut app_state = persistence::restore_session(&this.asset_dir).unwrap_or_default();
         let mut state_changed;
         let mut loaded_images: HashMap<String, Vec<u8>> = HashMap::new();
-        let mut uploads = Vec::new();
+        let mut images: HashSet<&String> = app_state.shown_images().collect();
+        for s in app_state.scenes.stored.values() {
+            images.extend(s.shown_images());
+        }
+        for name in images {
+            if let Ok(image) = std::fs::read(this.asset_dir.clone() + name) {
+                loaded_images.insert(name.clone(), image);
+            }
+        }
+        // images each client has been sent. a client only gets the images its own view
+        // shows, so journal handouts and gm layer images stay with whoever may see them
+        let mut sent_images: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut saver = AutoSaver::new(persistence::autosave_file(&this.asset_dir));
+        let mut history = History::new();
+        let mut rng = Rng::from_entropy();
+        let mut ping_limit = RateLimit::new(pointers::PING_INTERVAL);
+        let mut cursor_limit = RateLimit::new(pointers::CURSOR_INTERVAL);
+        // users whose last relayed cursor was on the board
+        let mut cursor_shown: HashSet<String> = HashSet::new();
+        // events meant for a single client, sent after the state broadcast
+        let mut replies: Vec<(String, EventData)> = Vec::new();
         'outer: loop {
             if SHOULD_DIE.load(std::sync::atomic::Ordering::Acquire) {
                 break;
             }
-            uploads.clear();
+            replies.clear();
             let mut events = Vec::new();
             for (name, con) in &mut this.clients {
                 let ev = Self::handle_client(should_log, name, con);
@@ -81,6 +320,28 @@ impl Server {
             }
             state_changed = false;
             for i in events {
+                // every event acts on the scene its sender is on
+                app_state.go_home();
+                app_state.enter_scene(&app_state.scene_of(&i.source));
+                let mut game_rules = rules::rules_by_name(&app_state.game.rules);
+                let touched = i.data.token_name().cloned();
+                // ids are sequential, so a player could name a gm or fogged token they were
+                // never sent. they get told it isn't there and their copy resyncs
+                if i.source != this.owner
+                    && let Some(name) = &touched
+                    && !app_state.shown_to_players(name)
+                {
+                    state_changed = true;
+                    replies.push((
+                        i.source.clone(),
+                        EventData::ActionRejected {
+                            name: name.clone(),
+                            reason: "there is no such token".into(),
+                        },
+                    ));
+                    continue;
+                }
+                let before = touched.as_ref().and_then(|n| app_state.snapshot(n));
                 match i.data {
                     EventData::Message {
                         from,
@@ -101,6 +362,7 @@ impl Server {
                         if should_log {
                             println!("{:#?} disconnected", username);
                         }
+                        sent_images.remove(&username);
                         this.clients.remove(&username);
                     }
                     EventData::Kill { password: _ } => {
@@ -117,61 +379,631 @@ impl Server {
                     }
                     EventData::ImageUpload { name, image } => {
                         state_changed = true;
-                        uploads.push(name.clone());
-                        let _ = std::fs::write(path().to_string() + &name, &image);
+                        // a replaced image goes out again, but never back to its uploader
+                        for (user, sent) in sent_images.iter_mut() {
+                            if *user != i.source {
+                                sent.remove(&name);
+                            }
+                        }
+                        sent_images
+                            .entry(i.source.clone())
+                            .or_default()
+                            .insert(name.clone());
+                        let _ = std::fs::write(this.asset_dir.clone() + &name, &image);
                         loaded_images.insert(name, image);
                     }
                     EventData::TokenMoved {
                         name,
-                        to,
+                        mut to,
                         time_stamp: _,
-                        layer,
+                        choice,
                     } => {
+                        // a refused move still resends the state so the mover's token jumps back
                         state_changed = true;
-                        if let Some(t) = app_state.tokens.get_mut(&name) {
-                            *t = to.clone();
+                        app_state.keep_owner(&name, &mut to, &i.source, &this.owner);
+                        app_state.keep_object(&name, &mut to);
+                        let moved = rules::try_move(
+                            game_rules.as_mut(),
+                            &mut app_state,
+                            &i.source,
+                            &name,
+                            &to,
+                            &choice,
+                        );
+                        if let Err(reason) = moved {
+                            replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected { name, reason },
+                            ));
                         }
-                        if let Some(t) = app_state.map.get_mut(&name) {
-                            *t = to.clone();
+                    }
+                    EventData::TokenCreatedAck {
+                        request: _,
+                        name: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::TokenUpdated { name, mut token } => {
+                        state_changed = true;
+                        if !change_allowed(
+                            game_rules.as_ref(),
+                            &app_state,
+                            &i.source,
+                            &name,
+                            &mut replies,
+                        ) {
+                            continue;
                         }
-                        if let Some(t) = app_state.gm.get_mut(&name) {
-                            *t = to.clone();
+                        app_state.keep_owner(&name, &mut token, &i.source, &this.owner);
+                        app_state.keep_object(&name, &mut token);
+                        app_state.keep_place(&name, &mut token);
+                        app_state.update_token(&name, &token);
+                    }
+                    EventData::TokenReordered { name, order } => {
+                        state_changed = true;
+                        if !change_allowed(
+                            game_rules.as_ref(),
+                            &app_state,
+                            &i.source,
+                            &name,
+                            &mut replies,
+                        ) {
+                            continue;
                         }
+                        app_state.reorder_token(&name, &order);
                     }
-                    EventData::SendState { state } => {
+                    EventData::TokenLayerChanged { name, layer } => {
                         state_changed = true;
-                        app_state = state;
+                        if layer == Layer::Gm && i.source != this.owner {
+                            replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected {
+                                    name,
+                                    reason: "only the gm can hide tokens on the gm layer".into(),
+                                },
+                            ));
+                            continue;
+                        }
+                        if !change_allowed(
+                            game_rules.as_ref(),
+                            &app_state,
+                            &i.source,
+                            &name,
+                            &mut replies,
+                        ) {
+                            continue;
+                        }
+                        app_state.change_token_layer(&name, &layer);
                     }
-                    EventData::TokenDestroyed { name, layer } => {
+                    EventData::SendState { state } => {
+                        // a map replaces tokens, fog and walls alike, so only the gm loads one
+                        if i.source != this.owner {
+                            replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected {
+                                    name: String::new(),
+                                    reason: "only the gm can load a map".into(),
+                                },
+                            ));
+                            continue;
+                        }
+                        // a loaded map becomes the sender's scene, the rest of the session stays
                         state_changed = true;
-                        app_state.map.remove(&name);
-                        app_state.tokens.remove(&name);
-                        app_state.gm.remove(&name);
+                        app_state.replace_scene(*state);
+                        history.clear();
+                    }
+                    EventData::SceneAdded { state } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.add_scene(*state);
+                        }
                     }
-                    EventData::TokenCreated { name, token, layer } => {
+                    EventData::JournalEntrySaved { id, entry } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            let id = if app_state.journal.contains_key(&id) {
+                                id
+                            } else {
+                                app_state.allocate_entry_id()
+                            };
+                            app_state.journal.insert(id, entry);
+                        }
+                    }
+                    EventData::JournalEntryRemoved { id } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.remove_entry(&id);
+                        }
+                    }
+                    EventData::NoteChanged { text } => {
                         state_changed = true;
-                        if !app_state.map.contains_key(&name)
-                            && !app_state.gm.contains_key(&name)
-                            && !app_state.tokens.contains_key(&name)
+                        app_state.notes.insert(i.source.clone(), text);
+                    }
+                    EventData::PinPlaced { entry, location } => {
+                        if i.source == this.owner && app_state.journal.contains_key(&entry) {
+                            state_changed = true;
+                            let id = app_state.allocate_pin_id();
+                            app_state.pins.insert(id, Pin { location, entry });
+                        }
+                    }
+                    EventData::PinRemoved { id } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.pins.remove(&id);
+                        }
+                    }
+                    EventData::SheetTemplateAdded { template } => {
+                        if i.source == this.owner && !template.name.is_empty() {
+                            state_changed = true;
+                            app_state
+                                .sheet_templates
+                                .insert(template.name.clone(), template);
+                        }
+                    }
+                    EventData::SheetAttached { token, template } => {
+                        if app_state.contains_token(&token)
+                            && app_state.controls(&token, &i.source, &this.owner)
                         {
-                            match layer {
-                                Layer::Base => {
-                                    app_state.tokens.insert(name, token);
+                            state_changed = true;
+                            match app_state.sheet_templates.get(&template) {
+                                Some(t) => {
+                                    app_state.sheets.insert(token, Sheet::new(t));
                                 }
-                                Layer::Map => {
-                                    app_state.map.insert(name, token);
+                                None => {
+                                    app_state.sheets.remove(&token);
                                 }
-                                Layer::Gm => {
-                                    app_state.gm.insert(name, token);
+                            }
+                        }
+                    }
+                    EventData::SheetChanged { token, key, value } => {
+                        if app_state.controls(&token, &i.source, &this.owner) {
+                            state_changed |= app_state.change_sheet(&token, &key, value);
+                        }
+                    }
+                    EventData::SheetRolled { token, roll } => {
+                        if app_state.controls(&token, &i.source, &this.owner) {
+                            match app_state.roll_sheet(&token, roll, &mut rng) {
+                                Ok(line) => {
+                                    state_changed = true;
+                                    app_state.messages.push((i.source.clone(), line));
                                 }
+                                Err(reason) => replies.push((
+                                    i.source.clone(),
+                                    EventData::ActionRejected {
+                                        name: token,
+                                        reason,
+                                    },
+                                )),
+                            }
+                        }
+                    }
+                    EventData::DiceRolled { expression } => {
+                        let no_fields = |k: &str| Err(format!("no field {} outside a sheet", k));
+                        match sheets::evaluate(&expression, &no_fields, Some(&mut rng)) {
+                            Ok((total, shown)) => {
+                                state_changed = true;
+                                app_state.messages.push((
+                                    i.source.clone(),
+                                    format!("rolled {} = {}", shown, total),
+                                ));
                             }
+                            Err(reason) => replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected {
+                                    name: expression,
+                                    reason,
+                                },
+                            )),
+                        }
+                    }
+                    // pings and cursors only go to the others on the sender's scene
+                    EventData::Ping { from: _, location } => {
+                        // i.source is the connection's user, whatever the client put in from
+                        if ping_limit.allow(&i.source, std::time::Instant::now()) {
+                            for to in app_state.same_scene(this.clients.keys(), &i.source) {
+                                let from = i.source.clone();
+                                replies.push((to.clone(), EventData::Ping { from, location }));
+                            }
+                        }
+                        continue;
+                    }
+                    EventData::CursorMoved { from: _, location } => {
+                        // the cursor leaving the board goes through once after each relayed
+                        // move, so it can't be used to skip the limit
+                        let relay = match location {
+                            Some(_) => {
+                                let allowed =
+                                    cursor_limit.allow(&i.source, std::time::Instant::now());
+                                if allowed {
+                                    cursor_shown.insert(i.source.clone());
+                                }
+                                allowed
+                            }
+                            None => cursor_shown.remove(&i.source),
+                        };
+                        if relay {
+                            for to in app_state.same_scene(this.clients.keys(), &i.source) {
+                                let from = i.source.clone();
+                                replies
+                                    .push((to.clone(), EventData::CursorMoved { from, location }));
+                            }
+                        }
+                        continue;
+                    }
+                    EventData::AreaPlaced { area } => {
+                        state_changed = true;
+                        let mut area = area.clamped();
+                        area.owner = i.source.clone();
+                        let id = app_state.allocate_area_id();
+                        app_state.areas.insert(id, area);
+                    }
+                    EventData::AreaRemoved { id } => {
+                        let allowed = app_state
+                            .areas
+                            .get(&id)
+                            .is_some_and(|a| a.owner == i.source || i.source == this.owner);
+                        if allowed {
+                            state_changed = true;
+                            app_state.areas.remove(&id);
+                        }
+                    }
+                    EventData::ScenePulled { scene } => {
+                        if i.source == this.owner && app_state.has_scene(&scene) {
+                            state_changed = true;
+                            app_state.scenes.players = scene;
+                            app_state.scenes.assigned.clear();
+                            history.clear();
+                        }
+                    }
+                    EventData::SceneAssigned { scene, users } => {
+                        if i.source == this.owner && app_state.has_scene(&scene) {
+                            state_changed = true;
+                            for u in users {
+                                if scene == app_state.scenes.players {
+                                    app_state.scenes.assigned.remove(&u);
+                                } else {
+                                    app_state.scenes.assigned.insert(u, scene.clone());
+                                }
+                            }
+                            history.clear();
+                        }
+                    }
+                    EventData::SceneRemoved { scene } => {
+                        if i.source == this.owner && scene != app_state.scenes.players {
+                            app_state.go_home();
+                            if app_state.scenes.stored.remove(&scene).is_some() {
+                                state_changed = true;
+                                app_state.scenes.assigned.retain(|_, s| *s != scene);
+                                history.clear();
+                            }
+                        }
+                    }
+                    EventData::FogChanged { cells, hidden } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.fog.set(&cells, hidden);
+                        }
+                    }
+                    EventData::FogEnabled { enabled } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.fog.enabled = enabled;
+                        }
+                    }
+                    EventData::WallCreated { wall } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            let id = format!("w{}", app_state.next_wall_id);
+                            app_state.next_wall_id += 1;
+                            app_state.walls.insert(id, wall);
+                        }
+                    }
+                    EventData::WallDestroyed { id } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.walls.remove(&id);
+                        }
+                    }
+                    EventData::DoorToggled { id } => {
+                        if let Some(w) = app_state.walls.get_mut(&id)
+                            && w.kind == WallKind::Door
+                        {
+                            state_changed = true;
+                            w.open = !w.open;
+                        }
+                    }
+                    EventData::VisionEnabled { enabled } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.dynamic_vision = enabled;
+                        }
+                    }
+                    EventData::DarknessChanged { darkness } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.darkness = darkness.clamp(0.0, 1.0);
+                        }
+                    }
+                    EventData::InitiativeAdded { token } => {
+                        if app_state.contains_token(&token)
+                            && app_state.controls(&token, &i.source, &this.owner)
+                        {
+                            state_changed = true;
+                            app_state.initiative.add(token, 0);
+                        }
+                    }
+                    EventData::InitiativeRemoved { token } => {
+                        if app_state.controls(&token, &i.source, &this.owner) {
+                            state_changed = true;
+                            app_state.initiative.remove(&token);
+                        }
+                    }
+                    EventData::InitiativeSet { token, initiative } => {
+                        if app_state.controls(&token, &i.source, &this.owner) {
+                            state_changed = true;
+                            let value = initiative.unwrap_or_else(|| rng.roll(20));
+                            app_state.initiative.set(&token, value);
+                        }
+                    }
+                    EventData::InitiativeSorted => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.initiative.sort();
+                        }
+                    }
+                    EventData::InitiativeCleared => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.initiative.clear();
+                        }
+                    }
+                    EventData::GameStarted {
+                        rules,
+                        players,
+                        setup,
+                    } => {
+                        if i.source == this.owner {
+                            // set up on a copy so a bad saved game leaves the board alone
+                            let mut next_rules = rules::rules_by_name(&rules);
+                            let mut next = app_state.clone();
+                            next.game = Game {
+                                rules: next_rules.name().to_string(),
+                                players,
+                                ..Default::default()
+                            };
+                            match next_rules.setup(&mut next, &setup) {
+                                Ok(()) => {
+                                    state_changed = true;
+                                    app_state = next;
+                                    history.clear();
+                                }
+                                Err(reason) => replies.push((
+                                    i.source.clone(),
+                                    EventData::ActionRejected {
+                                        name: rules,
+                                        reason,
+                                    },
+                                )),
+                            }
+                        }
+                    }
+                    EventData::ActionRejected { name: _, reason: _ } => {
+                        continue;
+                    }
+                    EventData::DeckCreated { mut deck } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            let id = app_state.allocate_deck_id();
+                            deck.discard.clear();
+                            app_state.decks.insert(id.clone(), deck);
+                            app_state.shuffle_deck(&id, rng.next_u64(), false);
+                        }
+                    }
+                    EventData::DeckMoved { deck, to } => {
+                        if let Some(d) = app_state.decks.get_mut(&deck) {
+                            state_changed = true;
+                            d.location = to;
+                        }
+                    }
+                    EventData::DeckDestroyed { deck } => {
+                        if i.source == this.owner {
+                            state_changed = true;
+                            app_state.decks.remove(&deck);
+                        }
+                    }
+                    EventData::DeckShuffled {
+                        deck,
+                        with_discards,
+                    } => {
+                        state_changed = true;
+                        app_state.shuffle_deck(&deck, rng.next_u64(), with_discards);
+                    }
+                    EventData::CardDrawn { deck } => {
+                        state_changed |= app_state.draw_card(&deck, &i.source);
+                    }
+                    EventData::CardPlayed {
+                        index,
+                        face_up,
+                        location,
+                    } => {
+                        let from = CardPlace::Hand { index };
+                        if let Some(card) = app_state.take_card(&from, &i.source, &this.owner) {
+                            state_changed = true;
+                            app_state.play_card(card, face_up, location, &i.source);
+                        }
+                    }
+                    EventData::CardDiscarded { from } => {
+                        if let Some(card) = app_state.take_card(&from, &i.source, &this.owner) {
+                            state_changed = true;
+                            app_state.discard_card(card);
+                        }
+                    }
+                    EventData::CardReturned { from } => {
+                        if let Some(card) = app_state.take_card(&from, &i.source, &this.owner) {
+                            state_changed = true;
+                            app_state.return_card(card);
                         }
                     }
+                    EventData::CardFlipped { name } => {
+                        if app_state.controls(&name, &i.source, &this.owner)
+                            && game_rules
+                                .check_change(&app_state, &i.source, &name)
+                                .is_ok()
+                        {
+                            state_changed = true;
+                            app_state.flip_card(&name);
+                        }
+                    }
+                    EventData::CardTaken { name } => {
+                        if game_rules
+                            .check_change(&app_state, &i.source, &name)
+                            .is_err()
+                        {
+                            continue;
+                        }
+                        let from = CardPlace::Board { name };
+                        if let Some(card) = app_state.take_card(&from, &i.source, &this.owner) {
+                            state_changed = true;
+                            app_state
+                                .hands
+                                .entry(i.source.clone())
+                                .or_default()
+                                .push(card);
+                        }
+                    }
+                    EventData::DieRolled { name } => {
+                        if game_rules
+                            .check_change(&app_state, &i.source, &name)
+                            .is_err()
+                        {
+                            continue;
+                        }
+                        if let Some(face) = app_state.roll_die(&name, &mut rng) {
+                            state_changed = true;
+                            app_state
+                                .messages
+                                .push((i.source.clone(), format!("rolled {}", face)));
+                        }
+                    }
+                    EventData::CounterChanged { name, by } => {
+                        if game_rules
+                            .check_change(&app_state, &i.source, &name)
+                            .is_err()
+                        {
+                            continue;
+                        }
+                        state_changed |= app_state.change_counter(&name, by);
+                    }
+                    EventData::TurnEnded => {
+                        let allowed = app_state
+                            .initiative
+                            .current()
+                            .is_some_and(|c| app_state.controls(&c.token, &i.source, &this.owner));
+                        if allowed {
+                            state_changed = true;
+                            app_state.initiative.next_turn();
+                        }
+                    }
+                    EventData::Undo { everyone } => {
+                        let everyone = everyone && i.source == this.owner;
+                        if game_rules.allows_undo() {
+                            state_changed |= history.undo(&mut app_state, &i.source, everyone);
+                        }
+                    }
+                    EventData::Redo { everyone } => {
+                        let everyone = everyone && i.source == this.owner;
+                        if game_rules.allows_undo() {
+                            state_changed |= history.redo(&mut app_state, &i.source, everyone);
+                        }
+                    }
+                    EventData::TokenDestroyed { name, layer: _ } => {
+                        state_changed = true;
+                        if !change_allowed(
+                            game_rules.as_ref(),
+                            &app_state,
+                            &i.source,
+                            &name,
+                            &mut replies,
+                        ) {
+                            continue;
+                        }
+                        app_state.destroy_token(&name);
+                        app_state.sheets.remove(&name);
+                    }
+                    EventData::TokenCreated {
+                        name,
+                        mut token,
+                        layer,
+                    } => {
+                        if let Err(reason) =
+                            game_rules.check_create(&app_state, &i.source, &token, &layer)
+                        {
+                            replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected { name, reason },
+                            ));
+                            continue;
+                        }
+                        state_changed = true;
+                        // cards only come onto the board from a deck, and new dice and
+                        // counters start out unrolled
+                        token.owner = i.source.clone();
+                        token.card.clear();
+                        token.object = token.object.fresh();
+                        let id = app_state.allocate_token_id();
+                        app_state.create_token(id.clone(), token, &layer);
+                        history.record(
+                            &i.source,
+                            &app_state.scene,
+                            id.clone(),
+                            None,
+                            app_state.snapshot(&id),
+                        );
+                        replies.push((
+                            i.source.clone(),
+                            EventData::TokenCreatedAck {
+                                request: name,
+                                name: id,
+                            },
+                        ));
+                    }
                     EventData::PersonalUpdate { people: _ } => {
                         continue;
                     }
+                    EventData::SaveState { name } => {
+                        if i.source != this.owner {
+                            continue;
+                        }
+                        if !persistence::valid_save_name(&name) {
+                            replies.push((
+                                i.source.clone(),
+                                EventData::ActionRejected {
+                                    reason: format!("{} can't be used as a save name", name),
+                                    name,
+                                },
+                            ));
+                            continue;
+                        }
+                        app_state.name = name;
+                        let pth = path().to_string() + &app_state.name + ".bored";
+                        if let Err(e) = persistence::save_state(&app_state, &pth, true) {
+                            println!("failed to save {}:{:#?}", pth, e);
+                        }
+                        state_changed = true;
+                    }
+                    EventData::Unknown => {
+                        if should_log {
+                            println!("unknown event from {:#?}", i.source);
+                        }
+                        continue;
+                    }
+                }
+                if let Some(name) = touched {
+                    let after = app_state.snapshot(&name);
+                    history.record(&i.source, &app_state.scene, name, before, after);
                 }
             }
+            app_state.go_home();
             let mut lck = match this.new_connections.lock() {
                 Ok(t) => t,
                 Err(t) => t.into_inner(),
@@ -214,17 +1046,9 @@ impl Server {
                     EventData::Connection { username } => {
                         state_changed = true;
                         if !this.clients.contains_key(&username) {
-                            for j in &loaded_images {
-                                let e = Event {
-                                    source: "_server".into(),
-                                    data: EventData::ImageUpload {
-                                        name: j.0.clone(),
-                                        image: j.1.clone(),
-                                    },
-                                };
-                                let _ = write_object(&mut i, &e);
-                            }
-                            if this.owner == "" {
+                            // images follow with the first state this client gets
+                            sent_images.remove(&username);
+                            if this.owner.is_empty() {
                                 this.owner = username.clone()
                             }
                             this.clients.insert(
@@ -252,13 +1076,202 @@ impl Server {
                         name: _,
                         to: _,
                         time_stamp: _,
-                        layer: _,
+                        choice: _,
                     } => {
                         continue;
                     }
+                    EventData::TokenUpdated { name: _, token: _ } => {
+                        continue;
+                    }
+                    EventData::TokenCreatedAck {
+                        request: _,
+                        name: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::TokenReordered { name: _, order: _ } => {
+                        continue;
+                    }
+                    EventData::TokenLayerChanged { name: _, layer: _ } => {
+                        continue;
+                    }
                     EventData::SendState { state: _ } => {
                         continue;
                     }
+                    EventData::SceneAdded { state: _ } => {
+                        continue;
+                    }
+                    EventData::ScenePulled { scene: _ } => {
+                        continue;
+                    }
+                    EventData::AreaPlaced { area: _ } => {
+                        continue;
+                    }
+                    EventData::Ping {
+                        from: _,
+                        location: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::CursorMoved {
+                        from: _,
+                        location: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::AreaRemoved { id: _ } => {
+                        continue;
+                    }
+                    EventData::SheetTemplateAdded { template: _ } => {
+                        continue;
+                    }
+                    EventData::SheetAttached {
+                        token: _,
+                        template: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::SheetChanged {
+                        token: _,
+                        key: _,
+                        value: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::SheetRolled { token: _, roll: _ } => {
+                        continue;
+                    }
+                    EventData::DiceRolled { expression: _ } => {
+                        continue;
+                    }
+                    EventData::JournalEntrySaved { id: _, entry: _ } => {
+                        continue;
+                    }
+                    EventData::JournalEntryRemoved { id: _ } => {
+                        continue;
+                    }
+                    EventData::NoteChanged { text: _ } => {
+                        continue;
+                    }
+                    EventData::PinPlaced {
+                        entry: _,
+                        location: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::PinRemoved { id: _ } => {
+                        continue;
+                    }
+                    EventData::SceneAssigned { scene: _, users: _ } => {
+                        continue;
+                    }
+                    EventData::SceneRemoved { scene: _ } => {
+                        continue;
+                    }
+                    EventData::FogChanged {
+                        cells: _,
+                        hidden: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::FogEnabled { enabled: _ } => {
+                        continue;
+                    }
+                    EventData::WallCreated { wall: _ } => {
+                        continue;
+                    }
+                    EventData::WallDestroyed { id: _ } => {
+                        continue;
+                    }
+                    EventData::DoorToggled { id: _ } => {
+                        continue;
+                    }
+                    EventData::VisionEnabled { enabled: _ } => {
+                        continue;
+                    }
+                    EventData::DarknessChanged { darkness: _ } => {
+                        continue;
+                    }
+                    EventData::InitiativeAdded { token: _ } => {
+                        continue;
+                    }
+                    EventData::InitiativeRemoved { token: _ } => {
+                        continue;
+                    }
+                    EventData::InitiativeSet {
+                        token: _,
+                        initiative: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::InitiativeSorted => {
+                        continue;
+                    }
+                    EventData::InitiativeCleared => {
+                        continue;
+                    }
+                    EventData::TurnEnded => {
+                        continue;
+                    }
+                    EventData::DieRolled { name: _ } => {
+                        continue;
+                    }
+                    EventData::CounterChanged { name: _, by: _ } => {
+                        continue;
+                    }
+                    EventData::GameStarted {
+                        rules: _,
+                        players: _,
+                        setup: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::ActionRejected { name: _, reason: _ } => {
+                        continue;
+                    }
+                    EventData::DeckCreated { deck: _ } => {
+                        continue;
+                    }
+                    EventData::DeckMoved { deck: _, to: _ } => {
+                        continue;
+                    }
+                    EventData::DeckDestroyed { deck: _ } => {
+                        continue;
+                    }
+                    EventData::DeckShuffled {
+                        deck: _,
+                        with_discards: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::CardDrawn { deck: _ } => {
+                        continue;
+                    }
+                    EventData::CardPlayed {
+                        index: _,
+                        face_up: _,
+                        location: _,
+                    } => {
+                        continue;
+                    }
+                    EventData::CardDiscarded { from: _ } => {
+                        continue;
+                    }
+                    EventData::CardReturned { from: _ } => {
+                        continue;
+                    }
+                    EventData::CardFlipped { name: _ } => {
+                        continue;
+                    }
+                    EventData::CardTaken { name: _ } => {
+                        continue;
+                    }
+                    EventData::Undo { everyone: _ } => {
+                        continue;
+                    }
+                    EventData::Redo { everyone: _ } => {
+                        continue;
+                    }
                     EventData::TokenDestroyed { name: _, layer: _ } => {
                         continue;
                     }
@@ -272,21 +1285,40 @@ impl Server {
                     EventData::PersonalUpdate { people: _ } => {
                         continue;
                     }
+                    EventData::SaveState { name: _ } => {
+                        continue;
+                    }
+                    EventData::Unknown => {
+                        continue;
+                    }
                 }
             }
             *lck = to_recheck;
             drop(lck);
             if state_changed {
-                let mut people: Vec<String> =
-                    this.clients.iter().map(|(i, _)| i.to_owned()).collect();
+                saver.mark_dirty();
+            }
+            saver.tick(should_log, &app_state);
+            if state_changed {
+                let mut people: Vec<String> = this.clients.keys().map(|i| i.to_owned()).collect();
                 people.sort_unstable();
+                // everyone gets the scene they are on. players never receive the tokens
+                // hidden under the fog, and nobody receives cards they aren't allowed to see.
+                // images follow the state, each client only getting those its view shows
                 for i in &mut this.clients {
+                    let state = app_state.view_for(i.0, *i.0 == this.owner);
+                    let sent = sent_images.entry(i.0.clone()).or_default();
+                    let images: Vec<String> = state
+                        .shown_images()
+                        .filter(|n| loaded_images.contains_key(*n) && sent.insert((*n).clone()))
+                        .cloned()
+                        .collect();
                     let _ = write_object(
                         &mut i.1.stream,
                         &Event {
                             source: "_server".into(),
                             data: EventData::SendState {
-                                state: app_state.clone(),
+                                state: Box::new(state),
                             },
                         },
                     );
@@ -300,22 +1332,33 @@ impl Server {
                             },
                         },
                     );
+                    for name in images {
+                        let image = loaded_images[&name].clone();
+                        let _ = write_object(
+                            &mut i.1.stream,
+                            &Event {
+                                source: "_server".into(),
+                                data: EventData::ImageUpload { name, image },
+                            },
+                        );
+                    }
                 }
-                for j in &uploads {
-                    let e = Event {
-                        source: "_server".into(),
-                        data: EventData::ImageUpload {
-                            name: j.clone(),
-                            image: loaded_images[j].clone(),
+            }
+            for (to, data) in replies.drain(..) {
+                if let Some(con) = this.clients.get_mut(&to) {
+                    let _ = write_object(
+                        &mut con.stream,
+                        &Event {
+                            source: "_server".into(),
+                            data,
                         },
-                    };
-
-                    for i in &mut this.clients {
-                        let _ = write_object(&mut i.1.stream, &e);
-                    }
+                    );
                 }
             }
         }
+        // a kill leaves the loop while the gm's scene is current
+        app_state.go_home();
+        saver.flush(should_log, &app_state);
         println!("died");
         SHOULD_DIE.store(true, std::sync::atomic::Ordering::Release);
         drop(this);
@@ -352,11 +1395,18 @@ impl Server {
         println!("died");
         EXISTS.store(false, std::sync::atomic::Ordering::Release);
     }
-    pub fn serve(should_log: bool) {
+    /// hosts a campaign, each campaign keeps its images and autosave in a folder of its own
+    pub fn serve(should_log: bool, campaign: &str) {
         SHOULD_DIE.store(false, std::sync::atomic::Ordering::Release);
+        let session = if campaign.trim().is_empty() {
+            "host".to_string()
+        } else {
+            format!("host-{}", campaign.trim())
+        };
         let server = Server {
             clients: HashMap::new(),
             owner: String::new(),
+            asset_dir: session_dir(&session),
             new_connections: Arc::new(Mutex::new(Vec::new())),
         };
         let connects = server.new_connections.clone();
@@ -364,3 +1414,94 @@ impl Server {
         Self::handle_clients(should_log, server, handle);
     }
 }
+#[test]
+pub fn z_order_test() {
+    let mut s = State::new();
+    for n in ["a", "b", "c"] {
+        s.create_token(n.into(), Token::default(), &Layer::Base);
+    }
+    assert_eq!(State::draw_order(&s.tokens), vec!["a", "b", "c"]);
+    s.reorder_token(&"c".into(), &ZOrder::Back);
+    s.reorder_token(&"a".into(), &ZOrder::Front);
+    assert_eq!(State::draw_order(&s.tokens), vec!["c", "b", "a"]);
+    s.change_token_layer(&"b".into(), &Layer::Gm);
+    assert_eq!(s.layer_of(&"b".into()), Some(Layer::Gm));
+    assert_eq!(State::draw_order(&s.tokens), vec!["c", "a"]);
+    s.initiative.add("a".into(), 10);
+    s.restore(&"a".into(), s.snapshot(&"a".into()));
+    assert_eq!(s.initiative.order.len(), 1);
+    s.destroy_token(&"a".into());
+    assert!(s.initiative.order.is_empty());
+}
+#[test]
+pub fn keep_owner_test() {
+    let mut s = State::new();
+    let (gm, player) = (String::from("gm"), String::from("player"));
+    let t = Token {
+        owner: player.clone(),
+        ..Default::default()
+    };
+    s.create_token("t".into(), t.clone(), &Layer::Base);
+    let mut stolen = Token {
+        owner: "thief".into(),
+        ..t.clone()
+    };
+    s.keep_owner(&"t".into(), &mut stolen, &player, &gm);
+    assert_eq!(stolen.owner, player);
+    let mut given = Token {
+        owner: "friend".into(),
+        ..t
+    };
+    s.keep_owner(&"t".into(), &mut given, &gm, &gm);
+    assert_eq!(given.owner, "friend");
+    let mut loaded = Token {
+        object: crate::dice::BoardObject::Counter { value: 99 },
+        card: "d".into(),
+        ..given
+    };
+    loaded.location = eframe::egui::Pos2::new(500.0, 500.0);
+    s.keep_object(&"t".into(), &mut loaded);
+    s.keep_place(&"t".into(), &mut loaded);
+    assert_eq!(loaded.location, s.tokens["t"].location);
+    assert_eq!(loaded.object, crate::dice::BoardObject::None);
+    assert!(loaded.card.is_empty());
+}
+#[test]
+pub fn claimed_source_test() -> crate::throws!() {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut client = TcpStream::connect(listener.local_addr()?)?;
+    let mut con = UserConnection {
+        username: "alice".into(),
+        stream: listener.accept()?.0,
+    };
+    for _ in 0..3 {
+        write_object(
+            &mut client,
+            &Event {
+                source: "someone else".into(),
+                data: EventData::Ping {
+                    from: "someone else".into(),
+                    location: eframe::egui::Pos2::ZERO,
+                },
+            },
+        )?;
+    }
+    let mut events = Vec::new();
+    for _ in 0..100 {
+        events.extend(Server::handle_client(false, "alice", &mut con));
+        if events.len() == 3 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(events.len(), 3);
+    assert!(events.iter().all(|e| e.source == "alice"));
+    let mut limit = RateLimit::new(pointers::PING_INTERVAL);
+    let now = std::time::Instant::now();
+    let allowed = events
+        .iter()
+        .filter(|e| limit.allow(&e.source, now))
+        .count();
+    assert_eq!(allowed, 1);
+    Ok(())
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::communication::State;
+use crate::utils::Rng;
+
+/// most dice and sides a single roll term may have, so a typo can't stall the server
+pub const MAX_DICE: i64 = 100;
+pub const MAX_SIDES: i64 = 1000;
+/// how deep formulas may refer to other formulas, deeper means a loop
+const MAX_DEPTH: usize = 16;
+/// longest expression and deepest brackets or minus signs the roller reads, rolls come
+/// from chat so anything longer is refused before it can exhaust the stack
+pub const MAX_EXPRESSION: usize = 200;
+const MAX_NESTING: usize = 32;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum FieldKind {
+    #[default]
+    Number,
+    Text,
+    /// worked out from the other fields, never typed in
+    Formula,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Field {
+    /// name formulas and rolls use to refer to the field
+    pub key: String,
+    pub label: String,
+    pub kind: FieldKind,
+    /// for formula fields, like "(strength - 10) / 2"
+    pub formula: String,
+    pub default: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RollButton {
+    pub label: String,
+    /// dice expression, like "1d20 + strength_mod"
+    pub roll: String,
+}
+
+/// what a sheet for some game system holds, loaded from a json file like
+/// {"name": "fighter", "fields": [{"key": "str", "label": "Strength"}], "rolls": [..]}
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SheetTemplate {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub rolls: Vec<RollButton>,
+}
+
+impl SheetTemplate {
+    pub fn field(&self, key: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.key == key)
+    }
+}
+
+/// a filled in sheet, attached to a token. whoever controls the token may edit it
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Sheet {
+    pub template: String,
+    /// typed in values by field key, fields missing here use the template's default
+    pub values: HashMap<String, String>,
+}
+
+impl Sheet {
+    pub fn new(template: &SheetTemplate) -> Self {
+        Sheet {
+            template: template.name.clone(),
+            values: HashMap::new(),
+        }
+    }
+    pub fn text(&self, field: &Field) -> String {
+        self.values
+            .get(&field.key)
+            .cloned()
+            .unwrap_or_else(|| field.default.clone())
+    }
+    /// the number a field stands for, formulas worked out
+    pub fn value(&self, template: &SheetTemplate, key: &str) -> Result<i64, String> {
+        self.value_at(template, key, 0)
+    }
+    fn value_at(&self, template: &SheetTemplate, key: &str, depth: usize) -> Result<i64, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("{} refers back to itself", key));
+        }
+        let field = template
+            .field(key)
+            .ok_or_else(|| format!("no field {}", key))?;
+        match field.kind {
+            FieldKind::Number => {
+                let text = self.text(field);
+                let text = text.trim();
+                if text.is_empty() {
+                    return Ok(0);
+                }
+                text.parse()
+                    .map_err(|_| format!("{} is not a number", field.label))
+            }
+            FieldKind::Text => Err(format!("{} is text", field.label)),
+            FieldKind::Formula => {
+                let vars = |k: &str| self.value_at(template, k, depth + 1);
+                evaluate(&field.formula, &vars, None).map(|(v, _)| v)
+            }
+        }
+    }
+    /// rolls one of the template's roll buttons, giving its label, the working and the total
+    pub fn roll(
+        &self,
+        template: &SheetTemplate,
+        index: usize,
+        rng: &mut Rng,
+    ) -> Result<(String, String, i64), String> {
+        let button = template.rolls.get(index).ok_or("no such roll")?;
+        let vars = |k: &str| self.value(template, k);
+        let (total, shown) = evaluate(&button.roll, &vars, Some(rng))?;
+        Ok((button.label.clone(), shown, total))
+    }
+}
+
+impl State {
+    /// sets a typed in field of a token's sheet, formulas can't be set
+    pub fn change_sheet(&mut self, token: &String, key: &str, value: String) -> bool {
+        let Some(sheet) = self.sheets.get_mut(token) else {
+            return false;
+        };
+        let typed = self
+            .sheet_templates
+            .get(&sheet.template)
+            .and_then(|t| t.field(key))
+            .is_some_and(|f| f.kind != FieldKind::Formula);
+        if typed {
+            sheet.values.insert(key.to_string(), value);
+        }
+        typed
+    }
+    /// players only get the sheets of tokens they own, the gm's npcs and other players'
+    /// characters stay with the gm
+    pub fn hide_sheets(&mut self, user: &String, gm: bool) {
+        if gm {
+            return;
+        }
+        let mut sheets = std::mem::take(&mut self.sheets);
+        sheets.retain(|name, _| self.get_token(name).is_some_and(|t| t.owner == *user));
+        self.sheets = sheets;
+    }
+    /// rolls a sheet's roll button, giving the chat line for it
+    pub fn roll_sheet(
+        &self,
+        token: &String,
+        index: usize,
+        rng: &mut Rng,
+    ) -> Result<String, String> {
+        let sheet = self.sheets.get(token).ok_or("the token has no sheet")?;
+        let template = self
+            .sheet_templates
+            .get(&sheet.template)
+            .ok_or_else(|| format!("there is no {} template", sheet.template))?;
+        let (label, shown, total) = sheet.roll(template, index, rng)?;
+        let who = self
+            .get_token(token)
+            .map(|t| t.display_name.clone())
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| token.clone());
+        Ok(format!("{} {}: {} = {}", who, label, shown, total))
+    }
+}
+
+/// works out an expression of whole numbers, field names, dice like 2d6 and + - * / with
+/// brackets. division rounds down. dice need an rng, the working shows each die rolled
+pub fn evaluate(
+    expression: &str,
+    vars: &dyn Fn(&str) -> Result<i64, String>,
+    rng: Option<&mut Rng>,
+) -> Result<(i64, String), String> {
+    if expression.chars().count() > MAX_EXPRESSION {
+        return Err(format!("rolls are at most {} characters", MAX_EXPRESSION));
+    }
+    let mut p = Parser {
+        chars: expression.chars().collect(),
+        at: 0,
+        nesting: 0,
+        vars,
+        rng,
+        shown: String::new(),
+    };
+    let value = p.sum()?;
+    p.skip_space();
+    if p.at < p.chars.len() {
+        return Err(format!("can't read {}", expression));
+    }
+    Ok((value, p.shown))
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    at: usize,
+    nesting: usize,
+    vars: &'a dyn Fn(&str) -> Result<i64, String>,
+    rng: Option<&'a mut Rng>,
+    shown: String,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.chars.get(self.at).copied()
+    }
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.at += 1;
+            self.shown += &format!(" {} ", op);
+            let rhs = self.product()?;
+            value = if op == '+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            }
+            .ok_or("the result is too big")?;
+        }
+        Ok(value)
+    }
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.at += 1;
+            self.shown += &format!(" {} ", op);
+            let rhs = self.factor()?;
+            value = if op == '*' {
+                value.checked_mul(rhs)
+            } else if rhs == 0 {
+                return Err("division by zero".into());
+            } else {
+                Some(value.div_euclid(rhs))
+            }
+            .ok_or("the result is too big")?;
+        }
+        Ok(value)
+    }
+    fn factor(&mut self) -> Result<i64, String> {
+        self.nesting += 1;
+        let value = self.nested_factor();
+        self.nesting -= 1;
+        value
+    }
+    fn nested_factor(&mut self) -> Result<i64, String> {
+        if self.nesting > MAX_NESTING {
+            return Err("too many brackets".into());
+        }
+        match self.peek() {
+            Some('-') => {
+                self.at += 1;
+                self.shown.push('-');
+                self.factor()?
+                    .checked_neg()
+                    .ok_or_else(|| "the result is too big".into())
+            }
+            Some('(') => {
+                self.at += 1;
+                self.shown.push('(');
+                let value = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err("missing )".into());
+                }
+                self.at += 1;
+                self.shown.push(')');
+                Ok(value)
+            }
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                let start = self.at;
+                while self
+                    .chars
+                    .get(self.at)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+                {
+                    self.at += 1;
+                }
+                let word: String = self.chars[start..self.at].iter().collect();
+                self.word(&word)
+            }
+            _ => Err("expected a number".into()),
+        }
+    }
+    /// a number, a die roll like 3d6 or d20, or a field
+    fn word(&mut self, word: &str) -> Result<i64, String> {
+        if let Ok(n) = word.parse::<i64>() {
+            self.shown += word;
+            return Ok(n);
+        }
+        if let Some((count, sides)) = word.split_once('d')
+            && count.chars().all(|c| c.is_ascii_digit())
+            && let Ok(sides) = sides.parse::<i64>()
+        {
+            let count = if count.is_empty() {
+                1
+            } else {
+                count.parse().map_err(|_| "too many dice")?
+            };
+            if !(1..=MAX_DICE).contains(&count) || !(1..=MAX_SIDES).contains(&sides) {
+                return Err(format!("can't roll {}", word));
+            }
+            let rng = self.rng.as_mut().ok_or("dice only go in rolls")?;
+            let rolled: Vec<i64> = (0..count).map(|_| rng.roll(sides as u32) as i64).collect();
+            let list: Vec<String> = rolled.iter().map(|r| r.to_string()).collect();
+            self.shown += &format!("{}[{}]", word, list.join(", "));
+            return Ok(rolled.iter().sum());
+        }
+        let value = (self.vars)(word)?;
+        self.shown += &format!("{}({})", word, value);
+        Ok(value)
+    }
+}
+
+#[test]
+pub fn sheet_formula_test() {
+    let template: SheetTemplate = serde_json::from_str(
+        r#"{
+            "name": "fighter",
+            "fields": [
+                {"key": "name", "label": "Name", "kind": "Text"},
+                {"key": "str", "label": "Strength", "default": "10"},
+                {"key": "str_mod", "label": "Str mod", "kind": "Formula", "formula": "(str - 10) / 2"},
+                {"key": "loop", "label": "Loop", "kind": "Formula", "formula": "loop + 1"}
+            ],
+            "rolls": [{"label": "attack", "roll": "1d20 + str_mod"}]
+        }"#,
+    )
+    .unwrap();
+    let mut sheet = Sheet::new(&template);
+    assert_eq!(sheet.value(&template, "str_mod"), Ok(0));
+    sheet.values.insert("str".into(), "7".into());
+    assert_eq!(sheet.value(&template, "str_mod"), Ok(-2));
+    assert!(sheet.value(&template, "loop").is_err());
+    assert!(sheet.value(&template, "name").is_err());
+    let mut rng = Rng::new(1);
+    for _ in 0..20 {
+        let (label, shown, total) = sheet.roll(&template, 0, &mut rng).unwrap();
+        assert_eq!(label, "attack");
+        assert!(shown.starts_with("1d20["));
+        assert!((-1..=18).contains(&total));
+    }
+    let none = |_: &str| Err::<i64, String>("no fields".into());
+    assert_eq!(
+        evaluate("2 * (3 + 4) - -1", &none, None).map(|r| r.0),
+        Ok(15)
+    );
+    assert!(evaluate("1d6", &none, None).is_err());
+    assert!(evaluate("1000d6", &none, Some(&mut rng)).is_err());
+    assert!(evaluate("3 +", &none, None).is_err());
+    let deep = "(".repeat(100_000) + "1" + &")".repeat(100_000);
+    assert!(evaluate(&deep, &none, None).is_err());
+    assert!(evaluate(&"-".repeat(150), &none, None).is_err());
+    let mut state = State::new();
+    let player = String::from("player");
+    for (name, owner) in [("hero", player.as_str()), ("goblin", "gm")] {
+        state.tokens.insert(
+            name.into(),
+            crate::communication::Token {
+                owner: owner.into(),
+                ..Default::default()
+            },
+        );
+        state.sheets.insert(name.into(), sheet.clone());
+    }
+    let seen = state.for_client(&player, false);
+    assert!(seen.sheets.contains_key("hero") && !seen.sheets.contains_key("goblin"));
+    assert_eq!(state.for_client(&"gm".into(), true).sheets.len(), 2);
+}
@@ -25,7 +25,7 @@ macro_rules! throw {
 #[macro_export]
 macro_rules! try_catch {
     ($to_try:block catch |$exp:ident|  $catch:block) => {
-        if let Err($exp) = ((|| {$to_try Ok::<(), Box<dyn std::error::Error>>(())}))() $catch
+        if let Err($exp) = (|| {$to_try Ok::<(), Box<dyn std::error::Error>>(())})() $catch
     };
     (($to_try:expr) catch|$exp:ident|  $catch:block) => {
         if let Err($exp) = (|| $to_try)() $catch
@@ -63,6 +63,7 @@ impl<T: Debug> Debug for Exception<T> {
 }
 impl<T: Debug> std::error::Error for Exception<T> {}
 pub trait AsErr<T> {
+    #[allow(clippy::wrong_self_convention)]
     fn as_err(self) -> throws!(T);
 }
 impl<T> AsErr<T> for Option<T> {
@@ -143,7 +144,45 @@ pub fn write_object<T: Serialize>(stream: &mut TcpStream, v: &T) -> throws!() {
     let _ = stream.set_nonblocking(false);
     let s = serde_json::to_string(v)?;
     let size: [u8; 8] = u64::to_ne_bytes((s.len() as u64).to_le());
-    stream.write(&size)?;
-    stream.write(s.as_bytes())?;
+    stream.write_all(&size)?;
+    stream.write_all(s.as_bytes())?;
     Ok(())
 }
+
+/// small seedable random number generator (splitmix64), good enough for dice and shuffles
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    /// seeded from the randomly keyed std hasher
+    pub fn from_entropy() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+        let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+        h.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        );
+        Self::new(h.finish())
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    /// a number in 0..n, n has to be above 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+    /// rolls one die with the given number of sides
+    pub fn roll(&mut self, sides: u32) -> i32 {
+        self.below(sides.max(1) as u64) as i32 + 1
+    }
+}
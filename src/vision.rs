@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+
+use eframe::egui::{Pos2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::communication::*;
+use crate::fog::{self, CELL, Cell};
+
+/// rays cast around a token besides the ones aimed at wall ends
+const BASE_RAYS: usize = 64;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum WallKind {
+    Wall,
+    Door,
+    /// can be seen through but not walked through
+    Window,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Wall {
+    pub a: Pos2,
+    pub b: Pos2,
+    pub kind: WallKind,
+    /// only meaningful for doors
+    pub open: bool,
+}
+
+impl Wall {
+    pub fn blocks_sight(&self) -> bool {
+        match self.kind {
+            WallKind::Wall => true,
+            WallKind::Door => !self.open,
+            WallKind::Window => false,
+        }
+    }
+    pub fn midpoint(&self) -> Pos2 {
+        self.a + (self.b - self.a) * 0.5
+    }
+}
+
+pub fn distance_to_segment(p: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let s = b - a;
+    let t = if s.length_sq() == 0.0 {
+        0.0
+    } else {
+        ((p - a).dot(s) / s.length_sq()).clamp(0.0, 1.0)
+    };
+    p.distance(a + s * t)
+}
+
+/// nearest grid corner, walls are drawn between corners
+pub fn snap_to_corner(p: Pos2) -> Pos2 {
+    let snap = |v: f32| fog::BOARD_MIN + ((v - fog::BOARD_MIN) / CELL).round() * CELL;
+    Pos2::new(snap(p.x), snap(p.y))
+}
+
+/// distance along the ray from origin in direction dir to the segment a-b, if it hits
+fn ray_hit(origin: Pos2, dir: Vec2, a: Pos2, b: Pos2) -> Option<f32> {
+    let s = b - a;
+    let denom = dir.x * s.y - dir.y * s.x;
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+    let d = a - origin;
+    let t = (d.x * s.y - d.y * s.x) / denom;
+    let u = (d.x * dir.y - d.y * dir.x) / denom;
+    if t >= 0.0 && (0.0..=1.0).contains(&u) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// polygon of everything visible from origin within radius, walls cut it short.
+/// rays go to both sides of every wall end so corners come out sharp
+pub fn visibility_polygon(origin: Pos2, radius: f32, walls: &[(Pos2, Pos2)]) -> Vec<Pos2> {
+    let mut angles: Vec<f32> = (0..BASE_RAYS)
+        .map(|i| i as f32 / BASE_RAYS as f32 * std::f32::consts::TAU)
+        .collect();
+    for (a, b) in walls {
+        for p in [a, b] {
+            if p.distance(origin) > radius {
+                continue;
+            }
+            let angle = (*p - origin).angle();
+            angles.extend([angle - 1e-4, angle, angle + 1e-4]);
+        }
+    }
+    angles.sort_by(|a, b| a.total_cmp(b));
+    angles
+        .into_iter()
+        .map(|angle| {
+            let dir = Vec2::angled(angle);
+            let dist = walls
+                .iter()
+                .filter_map(|(a, b)| ray_hit(origin, dir, *a, *b))
+                .fold(radius, f32::min);
+            origin + dir * dist
+        })
+        .collect()
+}
+
+impl State {
+    pub fn sight_blockers(&self) -> Vec<(Pos2, Pos2)> {
+        self.walls
+            .values()
+            .filter(|w| w.blocks_sight())
+            .map(|w| (w.a, w.b))
+            .collect()
+    }
+    /// visibility polygons of every token the user owns that has vision
+    pub fn vision_of(&self, user: &String) -> Vec<Vec<Pos2>> {
+        let walls = self.sight_blockers();
+        self.all_tokens()
+            .filter(|t| t.owner == *user && t.vision > 0.0)
+            .map(|t| {
+                let center = t.location + t.size() * 0.5;
+                visibility_polygon(center, t.vision * CELL, &walls)
+            })
+            .collect()
+    }
+    /// the grid cells a user can see, None when dynamic vision is off
+    pub fn visible_cells(&self, user: &String) -> Option<BTreeSet<Cell>> {
+        if !self.dynamic_vision {
+            return None;
+        }
+        let polygons = self.vision_of(user);
+        Some(
+            fog::all_cells()
+                .into_iter()
+                .filter(|c| {
+                    let p = fog::cell_rect(*c).center();
+                    polygons.iter().any(|poly| fog::point_in_polygon(p, poly))
+                })
+                .collect(),
+        )
+    }
+}
+
+#[test]
+pub fn wall_blocks_vision_test() {
+    let origin = Pos2::new(200.0, 200.0);
+    let wall = [(Pos2::new(240.0, 100.0), Pos2::new(240.0, 300.0))];
+    let poly = visibility_polygon(origin, 100.0, &wall);
+    assert!(fog::point_in_polygon(Pos2::new(230.0, 200.0), &poly));
+    assert!(!fog::point_in_polygon(Pos2::new(260.0, 200.0), &poly));
+    assert!(fog::point_in_polygon(Pos2::new(150.0, 200.0), &poly));
+}